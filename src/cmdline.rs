@@ -0,0 +1,113 @@
+//! # Kernel command line
+//! QEMU puts whatever it gets with `-append` into the bootargs property of
+//! the /chosen node of the device tree, and boots us with the address of
+//! the device tree in a1. We copy the command line out of there first
+//! thing in kinit, before the page allocator hands out the memory the
+//! device tree sits in.
+//!
+//! The command line is a list of options separated by spaces, each one
+//! either a plain word or `option=value`.
+use core::{
+    slice,
+    str,
+};
+
+// The device tree is big endian, see the devicetree specification.
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+
+/// The longest command line we keep. Anything after that is cut off.
+const CMDLINE_SIZE: usize = 256;
+
+static mut CMDLINE: [u8; CMDLINE_SIZE] = [0; CMDLINE_SIZE];
+static mut CMDLINE_LEN: usize = 0;
+
+unsafe fn be32(addr: usize) -> u32 {
+    u32::from_be((addr as *const u32).read())
+}
+
+/// The NUL terminated string at `addr`, without the NUL
+unsafe fn c_str(addr: usize) -> &'static [u8] {
+    let mut len = 0;
+    while (addr as *const u8).add(len).read() != 0 {
+        len += 1;
+    }
+    slice::from_raw_parts(addr as *const u8, len)
+}
+
+const fn align4(addr: usize) -> usize {
+    (addr + 3) & !3
+}
+
+/// Keep the command line from the device tree at `fdt`. Without a device
+/// tree, or without bootargs in it, the command line is empty.
+pub unsafe fn init(fdt: usize) {
+    if fdt == 0 || be32(fdt) != FDT_MAGIC {
+        return;
+    }
+    if let Some(bootargs) = find_bootargs(fdt) {
+        let len = bootargs.len().min(CMDLINE_SIZE);
+        CMDLINE[..len].copy_from_slice(&bootargs[..len]);
+        CMDLINE_LEN = len;
+    }
+}
+
+/// Walk the structure block for the bootargs property of /chosen.
+unsafe fn find_bootargs(fdt: usize) -> Option<&'static [u8]> {
+    let strings = fdt + be32(fdt + 12) as usize;
+    let mut at = fdt + be32(fdt + 8) as usize;
+    // The root node is at depth 1, so /chosen is at 2.
+    let mut depth = 0;
+    let mut in_chosen = false;
+    loop {
+        let token = be32(at);
+        at += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = c_str(at);
+                at = align4(at + name.len() + 1);
+                depth += 1;
+                if depth == 2 {
+                    in_chosen = name == b"chosen";
+                }
+            },
+            FDT_END_NODE => {
+                depth -= 1;
+            },
+            FDT_PROP => {
+                let len = be32(at) as usize;
+                let name = c_str(strings + be32(at + 4) as usize);
+                let value = at + 8;
+                at = align4(value + len);
+                if depth == 2 && in_chosen && name == b"bootargs" {
+                    // The value is a string, NUL included.
+                    return Some(c_str(value));
+                }
+            },
+            FDT_NOP => {},
+            // FDT_END, or something we don't understand
+            _ => return None,
+        }
+    }
+}
+
+/// The whole command line
+pub fn get() -> &'static str {
+    unsafe { str::from_utf8(&CMDLINE[..CMDLINE_LEN]).unwrap_or("") }
+}
+
+/// Whether `option` is on the command line, by itself or with a value
+pub fn has(option: &str) -> bool {
+    get().split(' ').any(|word| word.split('=').next() == Some(option))
+}
+
+/// The value `option` is given on the command line, as in `option=value`
+pub fn value(option: &str) -> Option<&'static str> {
+    get().split(' ').find_map(|word| {
+        let (name, value) = word.split_once('=')?;
+        if name == option { Some(value) } else { None }
+    })
+}
@@ -0,0 +1,331 @@
+//! # Console
+//! The UART is the console that every process starts out with as its
+//! standard input, output and error, and this is its terminal driver.
+//! Output goes straight out of the UART. Input comes in through the UART
+//! interrupt and goes through the line discipline in [`receive`], which
+//! echoes it, edits lines and turns ^C and ^Z into signals, and then
+//! waits here until someone reads it. What exactly happens is up to the
+//! [`Termios`] that userspace sets with ioctl.
+//!
+//! In canonical mode, readers only get whole lines, one per read. In raw
+//! mode, they get every byte as soon as it is typed.
+use alloc::{
+    collections::VecDeque,
+    vec::Vec,
+};
+
+use crate::{
+    process::with_table,
+    signal::{
+        send_group_signal,
+        SIGINT,
+        SIGQUIT,
+        SIGTSTP,
+    },
+    uart::Uart,
+};
+
+/// The console as a `FileDescriptor::Device`
+pub const CONSOLE: usize = 0;
+/// How many bytes of typed input we hold on to for readers. Anything
+/// typed beyond that is dropped.
+const INPUT_SIZE: usize = 1024;
+
+// ioctl requests, the same as Linux uses
+pub const TCGETS: usize = 0x5401;
+pub const TCSETS: usize = 0x5402;
+pub const TCSETSW: usize = 0x5403;
+pub const TCSETSF: usize = 0x5404;
+pub const TIOCGPGRP: usize = 0x540f;
+pub const TIOCSPGRP: usize = 0x5410;
+
+// Input flags
+pub const ICRNL: u32 = 0o400;
+// Output flags
+pub const OPOST: u32 = 0o1;
+pub const ONLCR: u32 = 0o4;
+// Control flags, which only mean something to real serial lines
+pub const CS8: u32 = 0o60;
+pub const CREAD: u32 = 0o200;
+// Local flags
+pub const ISIG: u32 = 0o1;
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+pub const ECHOE: u32 = 0o20;
+pub const ECHOK: u32 = 0o40;
+pub const NOFLSH: u32 = 0o200;
+
+// Special characters, as indices into Termios::cc
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VTIME: usize = 5;
+pub const VMIN: usize = 6;
+pub const VSUSP: usize = 10;
+pub const NCCS: usize = 19;
+
+/// The settings of the terminal, laid out like Linux' struct termios.
+/// A special character of 0 is switched off.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Termios {
+    pub iflag: u32,
+    pub oflag: u32,
+    pub cflag: u32,
+    pub lflag: u32,
+    pub line: u8,
+    pub cc: [u8; NCCS],
+}
+
+impl Termios {
+    /// What a shell expects: line editing, echo and signals
+    fn new() -> Self {
+        let mut cc = [0; NCCS];
+        cc[VINTR] = 0x03;
+        cc[VQUIT] = 0x1c;
+        cc[VERASE] = 0x7f;
+        cc[VKILL] = 0x15;
+        cc[VEOF] = 0x04;
+        cc[VMIN] = 1;
+        cc[VSUSP] = 0x1a;
+        Self {
+            iflag: ICRNL,
+            oflag: OPOST | ONLCR,
+            cflag: CS8 | CREAD,
+            lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK,
+            line: 0,
+            cc,
+        }
+    }
+
+    /// Whether `c` is the special character at `index`
+    fn is(&self, index: usize, c: u8) -> bool {
+        self.cc[index] != 0 && self.cc[index] == c
+    }
+}
+
+struct Tty {
+    termios: Termios,
+    /// What readers can have. In canonical mode, these are whole lines.
+    input: VecDeque<u8>,
+    /// In canonical mode, how long each line in `input` is. A line of
+    /// length 0 came from ^D on an empty line and reads as the end of the
+    /// file.
+    lines: VecDeque<usize>,
+    /// The line being edited in canonical mode
+    line: Vec<u8>,
+    /// The foreground process group, or 0 if there is none
+    pgrp: u16,
+    /// Processes waiting for input
+    waiting: Vec<u16>,
+}
+
+static mut TTY: Option<Tty> = None;
+
+fn tty() -> &'static mut Tty {
+    unsafe {
+        TTY.get_or_insert_with(|| Tty {
+            termios: Termios::new(),
+            input: VecDeque::new(),
+            lines: VecDeque::new(),
+            line: Vec::new(),
+            pgrp: 0,
+            waiting: Vec::new(),
+        })
+    }
+}
+
+impl Tty {
+    fn canonical(&self) -> bool {
+        self.termios.lflag & ICANON != 0
+    }
+
+    fn echo(&self, data: &[u8]) {
+        if self.termios.lflag & ECHO != 0 {
+            write(data);
+        }
+    }
+
+    /// Echo erasing the last `count` characters on the screen.
+    fn echo_erase(&self, count: usize) {
+        if self.termios.lflag & ECHOE != 0 {
+            for _ in 0..count {
+                self.echo(b"\x08 \x08");
+            }
+        }
+    }
+
+    /// Hand the line being edited over to the readers.
+    fn commit_line(&mut self) {
+        self.lines.push_back(self.line.len());
+        self.input.extend(self.line.drain(..));
+        self.wake_readers();
+    }
+
+    fn flush_input(&mut self) {
+        self.input.clear();
+        self.lines.clear();
+        self.line.clear();
+    }
+
+    fn wake_readers(&mut self) {
+        // A kernel process may have taken the process table, in which case
+        // nobody can be woken up right now. They'll get the next byte.
+        with_table(|table| {
+            for pid in self.waiting.drain(..) {
+                table.wake_up(pid);
+            }
+        });
+    }
+}
+
+/// Send `data` out of the UART.
+pub fn write(data: &[u8]) {
+    let termios = &tty().termios;
+    let onlcr = termios.oflag & (OPOST | ONLCR) == OPOST | ONLCR;
+    let mut uart = Uart::new(0x1000_0000);
+    for c in data {
+        if onlcr && *c == b'\n' {
+            uart.put(b'\r');
+        }
+        uart.put(*c);
+    }
+}
+
+/// The line discipline: take a byte that came in through the UART. This
+/// runs in the interrupt handler.
+pub fn receive(c: u8) {
+    let tty = tty();
+    let termios = tty.termios;
+    let c = if c == b'\r' && termios.iflag & ICRNL != 0 {
+        b'\n'
+    } else {
+        c
+    };
+    if termios.lflag & ISIG != 0 {
+        let sig = if termios.is(VINTR, c) {
+            SIGINT
+        } else if termios.is(VQUIT, c) {
+            SIGQUIT
+        } else if termios.is(VSUSP, c) {
+            SIGTSTP
+        } else {
+            0
+        };
+        if sig != 0 {
+            if termios.lflag & NOFLSH == 0 {
+                tty.flush_input();
+            }
+            tty.echo(&[b'^', c ^ 0x40, b'\n']);
+            // Like wakeups, the signal is lost if a kernel process holds
+            // the process list right now.
+            if tty.pgrp != 0 {
+                send_group_signal(tty.pgrp, sig);
+            }
+            return;
+        }
+    }
+    if !tty.canonical() {
+        if tty.input.len() < INPUT_SIZE {
+            tty.input.push_back(c);
+            tty.echo(&[c]);
+        }
+        tty.wake_readers();
+        return;
+    }
+    // Some terminals send ^H for backspace rather than DEL.
+    if termios.is(VERASE, c) || c == 0x08 {
+        if tty.line.pop().is_some() {
+            tty.echo_erase(1);
+        }
+    } else if termios.is(VKILL, c) {
+        let count = tty.line.len();
+        tty.line.clear();
+        if termios.lflag & ECHOK != 0 {
+            tty.echo_erase(count);
+        }
+    } else if tty.input.len() + tty.line.len() >= INPUT_SIZE {
+        // Not even a line can be finished until somebody reads.
+    } else if termios.is(VEOF, c) {
+        tty.commit_line();
+    } else if c == b'\n' {
+        // Lines stop one byte short, so there's always room to finish
+        // one, even when it got too long.
+        tty.line.push(c);
+        tty.echo(&[c]);
+        tty.commit_line();
+    } else if tty.input.len() + tty.line.len() < INPUT_SIZE - 1 {
+        tty.line.push(c);
+        tty.echo(&[c]);
+    }
+}
+
+/// Take input for `buffer` and return how much that was: a single line
+/// in canonical mode, or whatever there is in raw mode. Returns None if
+/// the reader has to wait for more. Reading 0 bytes is the end of the file.
+pub fn read(buffer: &mut [u8]) -> Option<usize> {
+    let tty = tty();
+    if buffer.is_empty() {
+        return Some(0);
+    }
+    let len = if tty.canonical() {
+        tty.lines.pop_front()?
+    } else if tty.input.is_empty() {
+        return None;
+    } else {
+        tty.input.len()
+    };
+    let count = buffer.len().min(len);
+    for (dst, src) in buffer.iter_mut().zip(tty.input.drain(..count)) {
+        *dst = src;
+    }
+    // The rest of the line is there for the next read.
+    if tty.canonical() && count < len {
+        tty.lines.push_front(len - count);
+    }
+    Some(count)
+}
+
+/// Wake `pid` up once there is more input.
+pub fn wait(pid: u16) {
+    let tty = tty();
+    if !tty.waiting.contains(&pid) {
+        tty.waiting.push(pid);
+    }
+}
+
+pub fn termios() -> Termios {
+    tty().termios
+}
+
+/// Change the settings, throwing away unread input if `flush` is set.
+/// Switching between canonical and raw mode keeps the input: a line
+/// being edited is handed to raw readers as it is, and raw input becomes
+/// a line for canonical readers.
+pub fn set_termios(termios: Termios, flush: bool) {
+    let tty = tty();
+    if flush {
+        tty.flush_input();
+    }
+    let was_canonical = tty.canonical();
+    tty.termios = termios;
+    if was_canonical && !tty.canonical() {
+        tty.lines.clear();
+        tty.input.extend(tty.line.drain(..));
+    } else if !was_canonical && tty.canonical() && !tty.input.is_empty() {
+        tty.lines.push_back(tty.input.len());
+    }
+    tty.wake_readers();
+}
+
+/// The foreground process group, which gets the signals typed at the
+/// console. This is 0 if there is none.
+pub fn foreground() -> u16 {
+    tty().pgrp
+}
+
+pub fn set_foreground(pgrp: u16) {
+    tty().pgrp = pgrp;
+}
@@ -22,10 +22,19 @@ use crate::{
         Process,
         ProcessData,
         ProcessState,
-        NEXT_PID,
         STACK_ADDR,
         STACK_PAGES,
     },
+    signal::Signals,
+    time::Times,
+    vma::{
+        Backing,
+        Vma,
+        VmaList,
+        PROT_EXEC,
+        PROT_READ,
+        PROT_WRITE,
+    },
     Buffer,
 };
 // Every ELF file starts with ELF "magic", which is a sequence of four bytes 0x7f followed by
@@ -142,9 +151,11 @@ impl File {
                 if ph.memsz == 0 {
                     continue;
                 }
-                let mut ph_buffer = Buffer::with_capacity(ph.memsz);
+                // Only filesz bytes are in the file. The rest of memsz is the
+                // BSS, which starts out zeroed.
+                let mut ph_buffer = Buffer::with_capacity(ph.filesz);
 
-                memcpy(ph_buffer.as_mut_ptr(), buffer.as_ptr().add(ph.off), ph.memsz);
+                memcpy(ph_buffer.as_mut_ptr(), buffer.as_ptr().add(ph.off), ph.filesz);
                 ret.programs.push_back(Program {
                     header: *ph,
                     data: ph_buffer,
@@ -154,8 +165,8 @@ impl File {
         Ok(ret)
     }
 
-    // load
-    pub fn load_proc(buffer: &Buffer) -> Result<Process, LoadErrors> {
+    // load the program into a new process with pid `my_pid`
+    pub fn load_proc(buffer: &Buffer, my_pid: u16) -> Result<Process, LoadErrors> {
         let elf_fl = Self::load(buffer);
         if elf_fl.is_err() {
             return Err(elf_fl.err().unwrap());
@@ -170,12 +181,6 @@ impl File {
         // necessitating the need for two extra pages. This can get wasteful, but for now
         // if we don't do this, we could end up mapping into the MMU table!
         let program_pages = (sz + PAGE_SIZE * 2) / PAGE_SIZE;
-        // I did this to demonstrate the expressive nature of Rust. Kinda cool, no?
-        let my_pid = unsafe {
-            let p = NEXT_PID + 1;
-            NEXT_PID += 1;
-            p
-        };
         let mut my_proc = Process {
             frame: zalloc(1) as *mut TrapFrame,
             stack: zalloc(STACK_PAGES),
@@ -185,9 +190,25 @@ impl File {
             data: ProcessData::new(),
             sleep_until: 0,
             program: zalloc(program_pages),
+            ppid: 0,
+            pgid: my_pid,
+            exit_status: 0,
+            interruptible: false,
+            brk_start: 0,
+            brk: 0,
+            vmas: VmaList::new(),
+            signals: Signals::new(),
+            trace: None,
+            times: Times::default(),
+            nice: 0,
         };
 
         let program_mem = my_proc.program;
+        // Every segment gets an area, which ends where the next one may start.
+        // The heap starts on the page after the last of them.
+        let mut areas_end = 0;
+        let mut last_bits = 0;
+        let mut last_prot = 0;
         let table = unsafe { my_proc.root.as_mut().unwrap() };
         // The ELF has several "program headers". This usually mimics the .text,
         // .rodata, .data, and .bss sections, but not necessarily.
@@ -197,40 +218,74 @@ impl File {
             // The program header table starts where the ELF header says it is
             // given by the field phoff (program header offset).
             // Copy the buffer we got from the filesystem into the program
-            // memory we're going to map to the user. The memsz field in the
+            // memory we're going to map to the user. The filesz field in the
             // program header tells us how many bytes will need to be loaded.
             // The ph.off is the offset to load this into.
             unsafe {
-                memcpy(program_mem.add(p.header.off), p.data.as_ptr(), p.header.memsz);
+                memcpy(program_mem.add(p.header.off), p.data.as_ptr(), p.header.filesz);
             }
             // We start off with the user bit set.
             let mut bits = EntryBits::User.val();
+            let mut prot = 0;
             // This sucks, but we check each bit in the flags to see
             // if we need to add it to the PH permissions.
             if p.header.flags & PROG_EXECUTE != 0 {
                 bits |= EntryBits::Execute.val();
+                prot |= PROT_EXEC;
             }
             if p.header.flags & PROG_READ != 0 {
                 bits |= EntryBits::Read.val();
+                prot |= PROT_READ;
             }
             if p.header.flags & PROG_WRITE != 0 {
                 bits |= EntryBits::Write.val();
+                prot |= PROT_WRITE;
             }
             // Now we map the program counter. The virtual address
-            // is provided in the ELF program header.
-            let pages = (PAGE_SIZE + align_val(p.header.memsz, 12)) / PAGE_SIZE;
-            for i in 0..pages {
-                let vaddr = p.header.vaddr + i * PAGE_SIZE;
+            // is provided in the ELF program header. We only map the pages
+            // that come from the file. The BSS pages after them are zeroed
+            // and mapped when the program first touches them. The BSS that
+            // shares the last page with the file data is zero already, since
+            // the program memory comes from zalloc.
+            let first_page = p.header.vaddr & !(PAGE_SIZE - 1);
+            let file_end = align_val(p.header.vaddr + p.header.filesz, 12);
+            for vaddr in (first_page..file_end).step_by(PAGE_SIZE) {
                 // The ELF specifies a paddr, but not when we
-                // use the vaddr!
-                let paddr = program_mem as usize + p.header.off + i * PAGE_SIZE;
+                // use the vaddr! The offset in the file and vaddr are
+                // the same modulo the page size.
+                let paddr = program_mem as usize + p.header.off + vaddr - p.header.vaddr;
+                // A page shared with the segment before us needs the
+                // permissions of both.
+                let bits = if vaddr < areas_end { bits | last_bits } else { bits };
                 // There is no checking here! This is very dangerous, and I have already
                 // been bitten by it. I mapped too far and mapped userspace into the MMU
                 // table, which is AWFUL!
                 map(table, vaddr, paddr, bits, 0);
                 // println!("DEBUG: Map 0x{:08x} to 0x{:08x} {:02x}", vaddr, paddr, bits);
             }
+            // The whole segment, BSS included, goes into the areas, so
+            // that faults on the BSS pages are known to be fine.
+            if first_page < areas_end {
+                my_proc
+                    .vmas
+                    .protect(first_page, areas_end, prot | last_prot, &mut |_| {});
+            }
+            let start = first_page.max(areas_end);
+            let end = align_val(p.header.vaddr + p.header.memsz, 12);
+            if start < end {
+                my_proc.vmas.insert(Vma {
+                    start,
+                    end,
+                    prot,
+                    backing: Backing::Anonymous,
+                });
+            }
+            areas_end = areas_end.max(end);
+            last_bits = bits;
+            last_prot = prot;
         }
+        my_proc.brk_start = areas_end;
+        my_proc.brk = areas_end;
         // This will map all of the program pages. Notice that in linker.lds in
         // userspace we set the entry point address to 0x2000_0000. This is the
         // same address as PROCESS_STARTING_ADDR, and they must match.
@@ -243,6 +298,13 @@ impl File {
             // This is why I don't need to make the stack executable.
             map(table, v_addr, p_addr, EntryBits::UserReadWrite.val(), 0);
         }
+        // The stack can grow down from here, see Process::handle_fault.
+        my_proc.vmas.insert(Vma {
+            start: STACK_ADDR,
+            end: STACK_ADDR + STACK_PAGES * PAGE_SIZE,
+            prot: PROT_READ | PROT_WRITE,
+            backing: Backing::Anonymous,
+        });
         // Set everything up in the trap frame
         unsafe {
             // The program counter is a virtual memory address and is loaded
@@ -261,10 +323,8 @@ impl File {
             // when the time comes.
             (*my_proc.frame).satp = build_satp(SatpMode::Sv39, my_proc.pid as usize, my_proc.root as usize);
         }
-        // The ASID field of the SATP register is only 16-bits, and we reserved
-        // 0 for the kernel, even though we run the kernel in machine mode for
-        // now. Since we don't reuse PIDs, this means that we can only spawn
-        // 65534 processes.
+        // The ASID is the pid, and pids are handed out again once they're free,
+        // so the TLB may still have entries from whoever had it before.
         satp_fence_asid(my_pid as usize);
         Ok(my_proc)
     }
@@ -0,0 +1,123 @@
+//! # Error numbers
+//! A system call that fails returns a negated error number in A0, the way
+//! Linux does it. newlib's system call stubs take anything from -4095 to
+//! -1 as an error and put it into `errno`, so the numbers are Linux' own.
+//! The errors of the file system, the block driver and the ELF loader all
+//! turn into one of these when they make it out to userspace.
+use crate::{
+    elf::LoadErrors,
+    fs::FsError,
+    uaccess::AccessError,
+    virtio::block::BlockErrors,
+};
+
+#[allow(clippy::upper_case_acronyms)]
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Errno {
+    /// Operation not permitted
+    EPERM = 1,
+    /// No such file or directory
+    ENOENT = 2,
+    /// No such process
+    ESRCH = 3,
+    /// I/O error
+    EIO = 5,
+    /// Argument list too long
+    E2BIG = 7,
+    /// Not an executable we can load
+    ENOEXEC = 8,
+    /// Bad file descriptor
+    EBADF = 9,
+    /// No children to wait for
+    ECHILD = 10,
+    /// Try again, for non-blocking descriptors
+    EAGAIN = 11,
+    /// Out of memory, or out of address space
+    ENOMEM = 12,
+    /// Permission denied
+    EACCES = 13,
+    /// Bad address
+    EFAULT = 14,
+    /// No such device
+    ENODEV = 19,
+    /// Not a directory
+    ENOTDIR = 20,
+    /// Is a directory
+    EISDIR = 21,
+    /// Invalid argument
+    EINVAL = 22,
+    /// Too many open files
+    EMFILE = 24,
+    /// Not a terminal
+    ENOTTY = 25,
+    /// Can't seek on a pipe or device
+    ESPIPE = 29,
+    /// Read-only file system
+    EROFS = 30,
+    /// Writing to a pipe nobody reads from
+    EPIPE = 32,
+    /// A buffer is too small for the result
+    ERANGE = 34,
+    /// File name too long
+    ENAMETOOLONG = 36,
+    /// No such system call
+    ENOSYS = 38,
+}
+
+impl Errno {
+    /// What a system call failing with this error returns in A0
+    pub const fn to_ret(self) -> usize {
+        (self as usize).wrapping_neg()
+    }
+}
+
+/// What a system call returns in A0: the value it came to, or its error.
+pub fn to_ret(result: Result<usize, Errno>) -> usize {
+    match result {
+        Ok(value) => value,
+        Err(errno) => errno.to_ret(),
+    }
+}
+
+impl From<FsError> for Errno {
+    fn from(error: FsError) -> Self {
+        match error {
+            FsError::FileNotFound => Self::ENOENT,
+            FsError::Permission => Self::EACCES,
+            FsError::IsFile => Self::ENOTDIR,
+            FsError::IsDirectory => Self::EISDIR,
+            // Nobody fails with this one, but it can't go through as 0.
+            FsError::Success => Self::EIO,
+        }
+    }
+}
+
+impl From<BlockErrors> for Errno {
+    fn from(error: BlockErrors) -> Self {
+        match error {
+            BlockErrors::BlockDeviceNotFound => Self::ENODEV,
+            BlockErrors::InvalidArgument => Self::EINVAL,
+            BlockErrors::ReadOnly => Self::EROFS,
+            BlockErrors::Success => Self::EIO,
+        }
+    }
+}
+
+impl From<LoadErrors> for Errno {
+    fn from(error: LoadErrors) -> Self {
+        match error {
+            LoadErrors::Magic | LoadErrors::Machine | LoadErrors::TypeExec => Self::ENOEXEC,
+            LoadErrors::FileRead => Self::EIO,
+        }
+    }
+}
+
+impl From<AccessError> for Errno {
+    fn from(error: AccessError) -> Self {
+        match error {
+            AccessError::Unmapped(_) | AccessError::Permission(_) => Self::EFAULT,
+            AccessError::TooLong(_) => Self::E2BIG,
+        }
+    }
+}
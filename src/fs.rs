@@ -2,6 +2,7 @@ use alloc::{
     boxed::Box,
     collections::BTreeMap,
     string::String,
+    vec::Vec,
 };
 use core::mem::size_of;
 
@@ -10,22 +11,88 @@ use crate::{
         memcpy,
         Registers,
     },
+    errno::Errno,
+    page::{
+        virt_to_phys,
+        Table,
+        PAGE_SIZE,
+    },
     process::{
         add_kernel_process_args,
         get_by_pid,
-        set_running,
-        set_waiting,
+        with_table,
+        FileDescriptor,
+        ProcessState,
     },
     syscall::syscall_block_read,
+    time,
+    uaccess::copy_to_user,
     Buffer,
 };
 
 pub const MAGIC: u16 = 0x4d5a;
 pub const BLOCK_SIZE: u32 = 1024;
 pub const NUM_IPTRS: usize = BLOCK_SIZE as usize / 4;
+pub const S_IFIFO: u16 = 0o010_000;
+pub const S_IFCHR: u16 = 0o020_000;
 pub const S_IFDIR: u16 = 0o040_000;
 pub const S_IFREG: u16 = 0o100_000;
 
+// Open flags as newlib passes them to openat. We can't write to the
+// file system yet, so only the access mode matters for files right now.
+pub const O_RDONLY: usize = 0;
+pub const O_WRONLY: usize = 1;
+pub const O_RDWR: usize = 2;
+pub const O_ACCMODE: usize = 3;
+pub const O_NONBLOCK: usize = 0x4000;
+pub const O_CLOEXEC: usize = 0x4_0000;
+// The dirfd that stands for the current directory in openat and friends
+pub const AT_FDCWD: isize = -100;
+// fstatat flag to stat dirfd itself when the path is empty
+pub const AT_EMPTY_PATH: usize = 0x1000;
+
+// Commands for fcntl
+pub const F_DUPFD: usize = 0;
+pub const F_GETFD: usize = 1;
+pub const F_SETFD: usize = 2;
+pub const F_GETFL: usize = 3;
+pub const F_SETFL: usize = 4;
+pub const F_DUPFD_CLOEXEC: usize = 14;
+// The only descriptor flag, for F_GETFD and F_SETFD
+pub const FD_CLOEXEC: usize = 1;
+
+// File types in the records getdents64 hands out
+pub const DT_UNKNOWN: u8 = 0;
+pub const DT_DIR: u8 = 4;
+pub const DT_REG: u8 = 8;
+
+// Whence values for lseek
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+/// Turn `path` into the absolute path that the inode cache knows it by.
+/// A relative path starts at directory `dir`, which is absolute itself.
+/// This also takes care of `.`, `..` and doubled up slashes. There are no
+/// symbolic links, so `..` simply drops the last component, and the root
+/// is its own parent.
+pub fn resolve_path(dir: &str, path: &str) -> String {
+    let mut parts = Vec::new();
+    let start = if path.starts_with('/') { "" } else { dir };
+    for part in start.split('/').chain(path.split('/')) {
+        match part {
+            "" | "." => {},
+            ".." => {
+                parts.pop();
+            },
+            part => parts.push(part),
+        }
+    }
+    let mut ret = String::from("/");
+    ret.push_str(&parts.join("/"));
+    ret
+}
+
 /// The superblock describes the file system on the disk. It gives
 /// us all the information we need to read the file system and navigate
 /// the file system, including where to find the inodes and zones (blocks).
@@ -65,6 +132,19 @@ pub struct Inode {
     pub zones: [u32; 10],
 }
 
+impl Inode {
+    /// Stamp the inode with the current time: it was read from, and
+    /// written to if `modified`.
+    pub fn touch(&mut self, modified: bool) {
+        let now = time::realtime_secs();
+        self.atime = now;
+        if modified {
+            self.mtime = now;
+            self.ctime = now;
+        }
+    }
+}
+
 /// Notice that an inode does not contain the name of a file. This is because
 /// more than one file name may refer to the same inode. These are called "hard links"
 /// Instead, a `DirEntry` essentially associates a file name with an inode as shown in
@@ -80,7 +160,8 @@ pub struct MinixFileSystem;
 // The plan for this in the future is to have a single inode cache. What we
 // will do is have a cache of Node structures which will combine the Inode
 // with the block drive.
-static mut MFS_INODE_CACHE: [Option<BTreeMap<String, Inode>>; 8] = [None, None, None, None, None, None, None, None];
+static mut MFS_INODE_CACHE: [Option<BTreeMap<String, (u32, Inode)>>; 8] =
+    [None, None, None, None, None, None, None, None];
 
 impl MinixFileSystem {
     /// Inodes are the meta-data of a file, including the mode (permissions and type) and
@@ -138,7 +219,7 @@ impl MinixFileSystem {
 impl MinixFileSystem {
     /// Init is where we would cache the superblock and inode to avoid having to read
     /// it over and over again, like we do for read right now.
-    fn cache_at(btm: &mut BTreeMap<String, Inode>, cwd: &str, inode_num: u32, bdev: usize) {
+    fn cache_at(btm: &mut BTreeMap<String, (u32, Inode)>, cwd: &str, inode_num: u32, bdev: usize) {
         let ino = Self::get_inode(bdev, inode_num).unwrap();
         let mut buf = Buffer::with_capacity(((ino.size + BLOCK_SIZE - 1) & !BLOCK_SIZE) as usize);
         let dirents = buf.as_ptr() as *const DirEntry;
@@ -167,9 +248,10 @@ impl MinixFileSystem {
                     new_cwd.push(d.name[i] as char);
                 }
                 new_cwd.shrink_to_fit();
-                if d_ino.mode & S_IFDIR == 0 {
-                    btm.insert(new_cwd, d_ino);
-                } else {
+                // Directories go into the cache too, so that we can chdir
+                // to them.
+                btm.insert(new_cwd.clone(), (d.inode, d_ino));
+                if d_ino.mode & S_IFDIR != 0 {
                     // This is a directory, cache these. This is a recursive call,
                     // which I don't really like.
                     Self::cache_at(btm, &new_cwd, d.inode, bdev);
@@ -186,6 +268,7 @@ impl MinixFileSystem {
 
             // Let's look at the root (inode #1)
             Self::cache_at(&mut btm, &cwd, 1, bdev);
+            btm.insert(cwd, (1, Self::get_inode(bdev, 1).unwrap()));
             unsafe {
                 MFS_INODE_CACHE[bdev - 1] = Some(btm);
             }
@@ -198,6 +281,12 @@ impl MinixFileSystem {
     /// in RAM, it might make this much quicker. For now, this doesn't do anything since
     /// we're just testing read based on if we know the Inode we're looking for.
     pub fn open(bdev: usize, path: &str) -> Result<Inode, FsError> {
+        Self::lookup(bdev, path).map(|(_, inode)| inode)
+    }
+
+    /// Find the inode at `path` along with its number, which is what
+    /// identifies a file on its device.
+    pub fn lookup(bdev: usize, path: &str) -> Result<(u32, Inode), FsError> {
         // if let Some(cache) = unsafe { MFS_INODE_CACHE[bdev - 1].take() } {
         //     let ret;
         //     if let Some(inode) = cache.get(path) {
@@ -214,8 +303,8 @@ impl MinixFileSystem {
         // }
         unsafe { MFS_INODE_CACHE[bdev - 1].take() }.map_or(Err(FsError::FileNotFound), |cache| {
             let ret;
-            if let Some(inode) = cache.get(path) {
-                ret = Ok(*inode);
+            if let Some(entry) = cache.get(path) {
+                ret = Ok(*entry);
             } else {
                 ret = Err(FsError::FileNotFound);
             }
@@ -226,6 +315,38 @@ impl MinixFileSystem {
         })
     }
 
+    /// Run `f` on the inode cache of `bdev`, unless it isn't there or
+    /// someone else has it right now.
+    fn with_cache<R>(bdev: usize, f: impl FnOnce(&mut BTreeMap<String, (u32, Inode)>) -> R) -> Option<R> {
+        let mut cache = unsafe { MFS_INODE_CACHE[bdev - 1].take() }?;
+        let ret = f(&mut cache);
+        unsafe {
+            MFS_INODE_CACHE[bdev - 1].replace(cache);
+        }
+        Some(ret)
+    }
+
+    /// Stamp inode `ino` on `bdev` with the current time, see
+    /// [`Inode::touch`]. We can't write inodes back to the disk, so the
+    /// times only last in the cache, which is where stat and open get
+    /// their inodes from.
+    pub fn touch(bdev: usize, ino: u32, modified: bool) {
+        Self::with_cache(bdev, |cache| {
+            for (_, inode) in cache.values_mut().filter(|(num, _)| *num == ino) {
+                inode.touch(modified);
+            }
+        });
+    }
+
+    /// The cached inode `ino` on `bdev`, with the times it got since it
+    /// was read from the disk.
+    pub fn cached_inode(bdev: usize, ino: u32) -> Option<Inode> {
+        Self::with_cache(bdev, |cache| {
+            cache.values().find(|(num, _)| *num == ino).map(|&(_, inode)| inode)
+        })
+        .flatten()
+    }
+
     pub fn read(bdev: usize, inode: &Inode, buffer: *mut u8, size: u32, offset: u32) -> u32 {
         // Our strategy here is to use blocks to see when we need to start reading
         // based on the offset. That's offset_block. Then, the actual byte within
@@ -493,12 +614,22 @@ impl MinixFileSystem {
         0
     }
 
-    pub const fn stat(&self, inode: &Inode) -> Stat {
+    /// Stat inode number `ino` on block device `bdev`.
+    pub fn stat(bdev: usize, ino: u32, inode: &Inode) -> Stat {
         Stat {
-            mode: inode.mode,
-            size: inode.size,
-            uid: inode.uid,
-            gid: inode.gid,
+            dev: bdev as u64,
+            ino: ino as u64,
+            mode: inode.mode as u32,
+            nlink: inode.nlinks as u32,
+            uid: inode.uid as u32,
+            gid: inode.gid as u32,
+            size: inode.size as i64,
+            blksize: BLOCK_SIZE as i32,
+            blocks: (inode.size as i64 + 511) / 512,
+            atime: inode.atime as i64,
+            mtime: inode.mtime as i64,
+            ctime: inode.ctime as i64,
+            ..Stat::default()
         }
     }
 }
@@ -508,7 +639,7 @@ impl MinixFileSystem {
 /// This allows me to do other things before I call the system call (or after).
 /// However, all the things I wanted to do are no longer there, so this
 /// is a worthless function.
-fn syc_read(bdev: usize, buffer: *mut u8, size: u32, offset: u32) -> u8 {
+fn syc_read(bdev: usize, buffer: *mut u8, size: u32, offset: u32) -> usize {
     syscall_block_read(bdev, buffer, size, offset)
 }
 
@@ -517,11 +648,12 @@ fn syc_read(bdev: usize, buffer: *mut u8, size: u32, offset: u32) -> u8 {
 // interrupt context.
 struct ProcArgs {
     pub pid: u16,
+    pub fd: u16,
     pub dev: usize,
-    pub buffer: *mut u8,
+    pub buffer: usize,
     pub size: u32,
     pub offset: u32,
-    pub node: u32,
+    pub node: Inode,
 }
 
 // This is the actual code ran inside of the read process.
@@ -529,30 +661,45 @@ fn read_proc(args_addr: usize) {
     let args = unsafe { Box::from_raw(args_addr as *mut ProcArgs) };
 
     // Start the read! Since we're in a kernel process, we can block by putting this
-    // process into a waiting state and wait until the block driver returns.
-    let inode = MinixFileSystem::get_inode(args.dev, args.node);
-    let bytes = MinixFileSystem::read(args.dev, &inode.unwrap(), args.buffer, args.size, args.offset);
+    // process into a waiting state and wait until the block driver returns. We read
+    // into our own buffer, since the user's buffer is virtual memory that can be
+    // scattered all over physical memory.
+    let mut buffer = Buffer::with_capacity(args.size as usize);
+    let bytes = MinixFileSystem::read(args.dev, &args.node, buffer.as_mut_ptr(), args.size, args.offset);
 
-    // Let's write the return result into regs[10], which is A0.
+    // Let's copy the data out, write the return result into regs[10], which is A0,
+    // and move the descriptor's offset past what we just read.
     unsafe {
         let ptr = get_by_pid(args.pid);
         if !ptr.is_null() {
-            (*(*ptr).get_frame_mut()).regs[Registers::A0 as usize] = bytes as usize;
+            let frame = (*ptr).get_frame_mut();
+            if copy_to_user(frame, args.buffer, buffer.as_ptr(), bytes as usize).is_ok() {
+                (*frame).regs[Registers::A0 as usize] = bytes as usize;
+                if let Some(FileDescriptor::File(f)) = (*ptr).data.fdesc.get(&args.fd) {
+                    let mut f = f.borrow_mut();
+                    f.offset += bytes as usize;
+                    MinixFileSystem::touch(f.bdev, f.ino, false);
+                }
+            } else {
+                (*frame).regs[Registers::A0 as usize] = Errno::EFAULT.to_ret();
+            }
         }
     }
     // This is the process making the system call. The system itself spawns another process
     // which goes out to the block device. Since we're passed the read call, we need to awaken
     // the process and get it ready to go. The only thing this process needs to clean up is the
     // tfree(), but the user process doesn't care about that.
-    set_running(args.pid);
+    with_table(|table| table.set_state(args.pid, ProcessState::Running));
 }
 
 /// System calls will call process_read, which will spawn off a kernel process to read
-/// the requested data.
-pub fn process_read(pid: u16, dev: usize, node: u32, buffer: *mut u8, size: u32, offset: u32) {
+/// the requested data into the user buffer at virtual address `buffer`. When the read
+/// finishes, the offset of descriptor `fd` is advanced by the number of bytes read.
+pub fn process_read(pid: u16, fd: u16, dev: usize, node: Inode, buffer: usize, size: u32, offset: u32) {
     // println!("FS read {}, {}, 0x{:x}, {}, {}", pid, dev, buffer as usize, size, offset);
     let args = ProcArgs {
         pid,
+        fd,
         dev,
         buffer,
         size,
@@ -560,19 +707,183 @@ pub fn process_read(pid: u16, dev: usize, node: u32, buffer: *mut u8, size: u32,
         node,
     };
     let boxed_args = Box::new(args);
-    set_waiting(pid);
+    with_table(|table| table.set_state(pid, ProcessState::Waiting));
     let _ = add_kernel_process_args(read_proc, Box::into_raw(boxed_args) as usize);
 }
 
+struct GetdentsArgs {
+    pub pid: u16,
+    pub fd: u16,
+    pub dev: usize,
+    pub buffer: usize,
+    pub size: usize,
+    pub offset: usize,
+    pub node: Inode,
+}
+
+// The kernel process that reads a directory for getdents64. The directory
+// is an array of DirEntry records, and the descriptor's offset is where in
+// that array we left off.
+fn getdents_proc(args_addr: usize) {
+    let args = unsafe { Box::from_raw(args_addr as *mut GetdentsArgs) };
+    let mut buffer = Buffer::with_capacity(args.node.size as usize);
+    let bytes = MinixFileSystem::read(args.dev, &args.node, buffer.as_mut_ptr(), args.node.size, 0) as usize;
+    let dirents = buffer.as_ptr() as *const DirEntry;
+    let num_dirents = bytes / size_of::<DirEntry>();
+    let mut out = Buffer::with_capacity(args.size);
+    let mut next = args.offset / size_of::<DirEntry>();
+    while next < num_dirents {
+        let d = unsafe { &*dirents.add(next) };
+        // Entries of removed files have no inode.
+        if d.inode != 0 {
+            let name_len = d.name.iter().position(|c| *c == 0).unwrap_or(d.name.len());
+            // A linux_dirent64 is the inode number, the offset of the next
+            // record, the record length, the file type and then the name
+            // with its NUL, padded to 8 bytes.
+            let reclen = (19 + name_len + 1 + 7) & !7;
+            if out.len() + reclen > args.size {
+                break;
+            }
+            let kind = match MinixFileSystem::get_inode(args.dev, d.inode) {
+                Some(ino) if ino.mode & S_IFDIR != 0 => DT_DIR,
+                Some(ino) if ino.mode & S_IFREG != 0 => DT_REG,
+                _ => DT_UNKNOWN,
+            };
+            let start = out.len();
+            out.extend_from_slice(&(d.inode as u64).to_ne_bytes());
+            out.extend_from_slice(&(((next + 1) * size_of::<DirEntry>()) as i64).to_ne_bytes());
+            out.extend_from_slice(&(reclen as u16).to_ne_bytes());
+            out.push(kind);
+            out.extend_from_slice(&d.name[..name_len]);
+            out.resize(start + reclen, 0);
+        }
+        next += 1;
+    }
+    unsafe {
+        let ptr = get_by_pid(args.pid);
+        if !ptr.is_null() {
+            let frame = (*ptr).get_frame_mut();
+            // Not even one record fitting into the buffer is an error, the
+            // end of the directory isn't.
+            let ret = if out.is_empty() && next < num_dirents {
+                Errno::EINVAL.to_ret()
+            } else if copy_to_user(frame, args.buffer, out.as_ptr(), out.len()).is_ok() {
+                if let Some(FileDescriptor::File(f)) = (*ptr).data.fdesc.get(&args.fd) {
+                    let mut f = f.borrow_mut();
+                    f.offset = next * size_of::<DirEntry>();
+                    MinixFileSystem::touch(f.bdev, f.ino, false);
+                }
+                out.len()
+            } else {
+                Errno::EFAULT.to_ret()
+            };
+            (*frame).regs[Registers::A0 as usize] = ret;
+        }
+    }
+    with_table(|table| table.set_state(args.pid, ProcessState::Running));
+}
+
+/// getdents64 on the directory `node`, open as descriptor `fd`. Like
+/// [`process_read`], this is done by a kernel process, which fills in up to
+/// `size` bytes of records at `buffer` starting from `offset` and moves
+/// the descriptor past them.
+pub fn process_getdents(pid: u16, fd: u16, dev: usize, node: Inode, buffer: usize, size: usize, offset: usize) {
+    let args = GetdentsArgs {
+        pid,
+        fd,
+        dev,
+        buffer,
+        size,
+        offset,
+        node,
+    };
+    let boxed_args = Box::new(args);
+    with_table(|table| table.set_state(pid, ProcessState::Waiting));
+    let _ = add_kernel_process_args(getdents_proc, Box::into_raw(boxed_args) as usize);
+}
+
+struct MapArgs {
+    pub pid: u16,
+    pub dev: usize,
+    pub v_addr: usize,
+    pub size: u32,
+    pub offset: u32,
+    pub node: Inode,
+}
+
+// The kernel process that fills in a file mapping.
+fn map_proc(args_addr: usize) {
+    let args = unsafe { Box::from_raw(args_addr as *mut MapArgs) };
+    let mut buffer = Buffer::with_capacity(args.size as usize);
+    let bytes = MinixFileSystem::read(args.dev, &args.node, buffer.as_mut_ptr(), args.size, args.offset) as usize;
+    unsafe {
+        let ptr = get_by_pid(args.pid);
+        if !ptr.is_null() {
+            // The mapping may well be read-only to the process, so we
+            // can't go through copy_to_user. The pages are fresh ones
+            // made by map_area, so we copy straight into them instead.
+            let table = &*((*ptr).get_table_address() as *const Table);
+            let mut done = 0;
+            while done < bytes {
+                let v_addr = args.v_addr + done;
+                let chunk = (PAGE_SIZE - v_addr % PAGE_SIZE).min(bytes - done);
+                if let Some(p_addr) = virt_to_phys(table, v_addr) {
+                    memcpy(p_addr as *mut u8, buffer.as_ptr().add(done), chunk);
+                }
+                done += chunk;
+            }
+            (*(*ptr).get_frame_mut()).regs[Registers::A0 as usize] = args.v_addr;
+        }
+    }
+    with_table(|table| table.set_state(args.pid, ProcessState::Running));
+}
+
+/// mmap calls process_map to read `size` bytes of a file at `offset` into
+/// the pages it just mapped at `v_addr`. The process returns `v_addr`
+/// from mmap once they're filled in.
+pub fn process_map(pid: u16, dev: usize, node: Inode, v_addr: usize, size: u32, offset: u32) {
+    let args = MapArgs {
+        pid,
+        dev,
+        v_addr,
+        size,
+        offset,
+        node,
+    };
+    let boxed_args = Box::new(args);
+    with_table(|table| table.set_state(pid, ProcessState::Waiting));
+    let _ = add_kernel_process_args(map_proc, Box::into_raw(boxed_args) as usize);
+}
+
 /// Stats on a file. This generally mimics an inode
 /// since that's the information we want anyway.
 /// However, inodes are filesystem specific, and we
 /// want a more generic stat.
+/// This is what the stat system calls hand out, so it is laid out like the
+/// struct stat of Linux on RISC-V, which newlib expects from the kernel.
+/// Times are in seconds, and blocks are counted in 512 bytes.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
 pub struct Stat {
-    pub mode: u16,
-    pub size: u32,
-    pub uid: u16,
-    pub gid: u16,
+    pub dev: u64,
+    pub ino: u64,
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub rdev: u64,
+    pub pad1: u64,
+    pub size: i64,
+    pub blksize: i32,
+    pub pad2: i32,
+    pub blocks: i64,
+    pub atime: i64,
+    pub atime_nsec: i64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub ctime: i64,
+    pub ctime_nsec: i64,
+    pub reserved: [i32; 2],
 }
 
 pub enum FsError {
@@ -57,6 +57,8 @@ macro_rules! println {
 extern "C" fn eh_personality() {}
 
 /// Custom panic handler
+///
+/// QEMU exits with a failure, so that test runs see it.
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     print!("Aborting: ");
@@ -65,13 +67,13 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
     } else {
         println!("no information available.");
     }
-    abort();
+    power::fail(1);
 }
 
 /// Never return function that waits for interrupt
 ///
-/// Used in `panic` to handle end of kernel
-/// execution
+/// Used to handle end of kernel execution when there's
+/// no way to switch the machine off
 #[no_mangle]
 extern "C" fn abort() -> ! {
     loop {
@@ -90,39 +92,63 @@ extern "C" {
 /// Since it will jump to another program counter,
 /// it will never return back here. We don't care if we leak
 /// the stack, since we will recapture the stack during `m_trap`.
+/// Pending signals are delivered right before a process runs, and if one
+/// of them kills it, we pick another one instead. Whoever runs in the end
+/// gets a time slice that the scheduler decides on.
 fn rust_switch_to_user(frame: usize) -> ! {
+    let mut frame = frame;
     unsafe {
+        strace::finish(frame as *const cpu::TrapFrame);
+        while !signal::deliver(frame as *mut cpu::TrapFrame) {
+            frame = sched::schedule();
+        }
+        let pid = (*(frame as *const cpu::TrapFrame)).pid as u16;
+        let slice = process::with_table(|table| table.time_slice(pid)).flatten();
+        trap::schedule_next_context_switch(slice.unwrap_or(1));
+        time::leave_kernel();
         switch_to_user(frame);
     }
 }
 
-/// Kernel entry point
+/// Kernel entry point, with the address of the device tree
 #[no_mangle]
-extern "C" fn kinit() {
+extern "C" fn kinit(fdt: usize) {
+    // The device tree is in memory that's about to be handed out.
+    unsafe { cmdline::init(fdt) };
     uart::Uart::new(0x1000_0000).init();
     page::init();
     kmem::init();
+    if cfg!(feature = "syscall-header") {
+        print!("{}", syscall::header());
+        power::poweroff();
+    }
     process::init();
     // We lower the threshold wall so our interrupts can jump over it.
     // Any priority > 0 will be able to be "heard"
     plic::set_threshold(0);
     // VIRTIO = [1..8]
     // UART0 = 10
+    // RTC = 11
     // PCIE = [32..35]
     // Enable PLIC interrupts.
-    for i in 1..=10 {
+    for i in 1..=11 {
         plic::enable(i);
         plic::set_priority(i, 1);
     }
+    // Find out what the date is.
+    rtc::init();
     // Set up virtio. This requires a working heap and page-grained allocator.
     virtio::probe();
     // Test the block driver!
-    process::add_kernel_process(test::test);
+    let pid = process::add_kernel_process(test::test);
+    if cmdline::has("strace") {
+        // Trace the shell and everything it starts.
+        process::with_table(|table| table.get_mut(pid).map(|p| p.trace = Some(strace::Trace::new(true))));
+    }
     // Get the GPU going
     virtio::gpu::init(6);
-    // We schedule the next context switch using a multiplier of 1
     // Block testing code removed.
-    trap::schedule_next_context_switch(1);
+    // The first process to run schedules the next context switch.
     rust_switch_to_user(sched::schedule());
     // switch_to_user will not return, so we should never get here
 }
@@ -141,10 +167,16 @@ pub type Buffer = alloc::vec::Vec<u8>;
 pub mod assembly;
 /// Buffer management stuff
 // pub mod buffer;
+/// The kernel command line from the device tree
+pub mod cmdline;
+/// The UART as a file descriptor
+pub mod console;
 /// RISC-V cpu instructions wrapper
 pub mod cpu;
 /// Elf binary format execution
 pub mod elf;
+/// Error numbers for system calls
+pub mod errno;
 /// Minix3 file system implementation
 pub mod fs;
 /// Kernel memory management
@@ -153,19 +185,35 @@ pub mod kmem;
 pub mod lock;
 /// Paging and related functions implementation
 pub mod page;
+/// Pipes between processes
+pub mod pipe;
 /// Programmable interrupt controller functionality
 pub mod plic;
+/// Switching the machine off and rebooting
+pub mod power;
 /// Process data
 pub mod process;
+/// Goldfish real-time clock
+pub mod rtc;
 /// Process scheduling
 pub mod sched;
+/// Signal delivery
+pub mod signal;
+/// Logging the system calls of traced processes
+pub mod strace;
 /// System calls
 pub mod syscall;
 /// First initalized process
 pub mod test;
+/// Clocks, and the time processes spend running
+pub mod time;
 /// Trampoline for interrupts
 pub mod trap;
+/// Copying buffers and strings to and from user space
+pub mod uaccess;
 /// Universal Asynchronous Receiver-Transmitter
 pub mod uart;
 /// Virtual input/output protocol
 pub mod virtio;
+/// Virtual memory areas of a process
+pub mod vma;
@@ -3,6 +3,8 @@ use core::{
     ptr::null_mut,
 };
 
+use crate::cpu::memcpy;
+
 // ////////////////////////////////
 // // Allocation routines
 // ////////////////////////////////
@@ -46,6 +48,10 @@ impl PageBits {
 // associated with it. However, there structure is much larger.
 pub struct Page {
     flags: u8,
+    // How many holders this page has besides the first one. Pages
+    // shared copy-on-write between processes are only freed once
+    // this drops back to 0.
+    refs: u16,
 }
 
 impl Page {
@@ -69,6 +75,7 @@ impl Page {
     // Clear the Page structure and all associated allocations.
     pub fn clear(&mut self) {
         self.flags = PageBits::Empty.val();
+        self.refs = 0;
     }
 
     // Set a certain flag. We ran into trouble here since PageBits
@@ -198,16 +205,12 @@ pub fn dealloc(ptr: *mut u8) {
     // Make sure we don't try to free a null pointer.
     assert!(!ptr.is_null());
     unsafe {
-        let addr = HEAP_START + (ptr as usize - ALLOC_START) / PAGE_SIZE;
-        // Make sure that the address makes sense. The address we
-        // calculate here is the page structure, not the HEAP address!
-        assert!(addr >= HEAP_START && addr < ALLOC_START);
-        let mut p = addr as *mut Page;
+        let mut p = page_of(ptr as usize);
         // println!("PTR in is {:p}, addr is 0x{:x}", ptr, addr);
         assert!((*p).is_taken(), "Freeing a non-taken page?");
         // Keep clearing pages until we hit the last page.
         while (*p).is_taken() && !(*p).is_last() {
-            (*p).clear();
+            release(p);
             p = p.add(1);
         }
         // If the following assertion fails, it is most likely
@@ -218,7 +221,88 @@ pub fn dealloc(ptr: *mut u8) {
         );
         // If we get here, we've taken care of all previous pages and
         // we are on the last page.
+        release(p);
+    }
+}
+
+/// Get the Page structure that describes the page at `addr`.
+unsafe fn page_of(addr: usize) -> *mut Page {
+    // Make sure that the address makes sense. The address we
+    // calculate here is the page structure, not the HEAP address!
+    assert!(addr >= ALLOC_START, "Address 0x{:x} isn't on the heap", addr);
+    let p = (HEAP_START as *mut Page).add((addr - ALLOC_START) / PAGE_SIZE);
+    assert!((p as usize) < ALLOC_START);
+    p
+}
+
+/// Drop the first holder of a page while freeing its allocation. If
+/// someone else still shares it, the page stays taken, but on its own,
+/// so that the last holder can free it with [`put_page`].
+unsafe fn release(p: *mut Page) {
+    if (*p).refs == 0 {
         (*p).clear();
+    } else {
+        (*p).refs -= 1;
+        (*p).set_flag(PageBits::Last);
+    }
+}
+
+/// Split an allocation into single-page allocations, so that every page
+/// can be shared and freed on its own with [`get_page`] and
+/// [`put_page`].
+pub fn split(ptr: *mut u8) {
+    unsafe {
+        let mut p = page_of(ptr as usize);
+        assert!((*p).is_taken(), "Splitting a non-taken page?");
+        while !(*p).is_last() {
+            (*p).set_flag(PageBits::Last);
+            p = p.add(1);
+        }
+    }
+}
+
+/// Take another reference on the page at `addr`.
+pub fn get_page(addr: usize) {
+    unsafe {
+        let p = page_of(addr);
+        assert!((*p).is_taken(), "Sharing a non-taken page?");
+        (*p).refs += 1;
+    }
+}
+
+/// Drop a reference on the page at `addr` taken by [`get_page`]. The
+/// last one to let go frees the page.
+pub fn put_page(addr: usize) {
+    unsafe {
+        let p = page_of(addr);
+        assert!((*p).is_taken(), "Putting a non-taken page?");
+        if (*p).refs == 0 {
+            assert!((*p).is_last(), "Freeing a page that is part of an allocation");
+            (*p).clear();
+        } else {
+            (*p).refs -= 1;
+        }
+    }
+}
+
+/// How many holders the page at `addr` has besides the first one.
+pub fn page_refs(addr: usize) -> usize {
+    unsafe { (*page_of(addr)).refs as usize }
+}
+
+/// Count the pages of the allocation starting at `ptr` by
+/// walking its Page structures up to the one marked Last.
+pub fn allocation_size(ptr: *mut u8) -> usize {
+    assert!(!ptr.is_null());
+    unsafe {
+        let mut p = page_of(ptr as usize);
+        assert!((*p).is_taken(), "Sizing a non-taken page?");
+        let mut pages = 1;
+        while !(*p).is_last() {
+            pages += 1;
+            p = p.add(1);
+        }
+        pages
     }
 }
 
@@ -241,14 +325,14 @@ pub fn print_page_allocations() {
         while beg < end {
             if (*beg).is_taken() {
                 let start = beg as usize;
-                let memaddr = ALLOC_START + (start - HEAP_START) * PAGE_SIZE;
+                let memaddr = ALLOC_START + (start - HEAP_START) / size_of::<Page>() * PAGE_SIZE;
                 print!("0x{:x} => ", memaddr);
                 loop {
                     num += 1;
                     if (*beg).is_last() {
                         let end = beg as usize;
-                        let memaddr = ALLOC_START + (end - HEAP_START) * PAGE_SIZE + PAGE_SIZE - 1;
-                        print!("0x{:x}: {:>3} page(s)", memaddr, (end - start + 1));
+                        let memaddr = ALLOC_START + (end - HEAP_START) / size_of::<Page>() * PAGE_SIZE + PAGE_SIZE - 1;
+                        print!("0x{:x}: {:>3} page(s)", memaddr, (end - start) / size_of::<Page>() + 1);
                         println!(".");
                         break;
                     }
@@ -286,6 +370,12 @@ pub enum EntryBits {
     Global = 1 << 5,
     Access = 1 << 6,
     Dirty = 1 << 7,
+    // The two RSW bits are ours to use. CopyOnWrite marks a page that
+    // is writable, but shared read-only until someone stores to it.
+    // Owned marks a page that the mapping holds a reference on (see
+    // get_page/put_page), so it's dropped when the table is unmapped.
+    CopyOnWrite = 1 << 8,
+    Owned = 1 << 9,
 
     // Convenience combinations
     ReadWrite = 1 << 1 | 1 << 2,
@@ -344,6 +434,12 @@ impl Entry {
     pub const fn get_entry(&self) -> i64 {
         self.entry
     }
+
+    // The physical address this entry points to, either the page of a
+    // leaf or the next table of a branch.
+    pub const fn get_paddr(&self) -> usize {
+        ((self.get_entry() & !0x3ff) << 2) as usize
+    }
 }
 
 // Table represents a single table, which contains 512 (2^9), 64-bit entries.
@@ -436,6 +532,7 @@ pub fn map(root: &mut Table, v_addr: usize, p_addr: usize, bits: i64, level: usi
 }
 
 /// Unmaps and frees all memory associated with a table.
+/// Leaves marked Owned drop their reference on the page they map.
 /// root: The root table to start freeing.
 /// NOTE: This does NOT free root directly. This must be
 /// freed manually.
@@ -457,6 +554,12 @@ pub fn unmap(root: &mut Table) {
                 let entry_lv1 = &table_lv1.entries[lv1];
                 if entry_lv1.is_valid() && entry_lv1.is_branch() {
                     let memaddr_lv0 = (entry_lv1.get_entry() & !0x3ff) << 2;
+                    let table_lv0 = unsafe { &*(memaddr_lv0 as *const Table) };
+                    for entry_lv0 in table_lv0.entries.iter() {
+                        if entry_lv0.is_valid() && entry_lv0.get_entry() & EntryBits::Owned.val() != 0 {
+                            put_page(entry_lv0.get_paddr());
+                        }
+                    }
                     // The next level is level 0, which
                     // cannot have branches, therefore,
                     // we free here.
@@ -468,11 +571,127 @@ pub fn unmap(root: &mut Table) {
     }
 }
 
+/// Call `f(v_addr, entry)` for every 4 KiB leaf in the table. The
+/// entry may be changed, but the caller has to fence the TLB after.
+/// We only ever map with level 0, so leaves at the upper levels
+/// (giga and mega pages) are skipped.
+pub fn for_each_leaf(root: &mut Table, f: &mut dyn FnMut(usize, &mut Entry)) {
+    for lv2 in 0..Table::len() {
+        let entry_lv2 = &root.entries[lv2];
+        if entry_lv2.is_invalid() || entry_lv2.is_leaf() {
+            continue;
+        }
+        let table_lv1 = unsafe { &mut *(entry_lv2.get_paddr() as *mut Table) };
+        for lv1 in 0..Table::len() {
+            let entry_lv1 = &table_lv1.entries[lv1];
+            if entry_lv1.is_invalid() || entry_lv1.is_leaf() {
+                continue;
+            }
+            let table_lv0 = unsafe { &mut *(entry_lv1.get_paddr() as *mut Table) };
+            for lv0 in 0..Table::len() {
+                let entry_lv0 = &mut table_lv0.entries[lv0];
+                if entry_lv0.is_valid() {
+                    f(lv2 << 30 | lv1 << 21 | lv0 << 12, entry_lv0);
+                }
+            }
+        }
+    }
+}
+
+/// Find the level 0 leaf that maps `v_addr`, if there is one.
+fn leaf_mut(root: &mut Table, v_addr: usize) -> Option<&mut Entry> {
+    let vpn = [(v_addr >> 12) & 0x1ff, (v_addr >> 21) & 0x1ff, (v_addr >> 30) & 0x1ff];
+    let mut v = &mut root.entries[vpn[2]];
+    for i in (0..2).rev() {
+        if v.is_invalid() || v.is_leaf() {
+            return None;
+        }
+        let entry = v.get_paddr() as *mut Entry;
+        v = unsafe { entry.add(vpn[i]).as_mut().unwrap() };
+    }
+    if v.is_valid() { Some(v) } else { None }
+}
+
+/// Remove the leaf that maps `v_addr`, dropping its reference on the
+/// page if it holds one. Returns false if nothing was mapped there.
+/// The caller has to fence the TLB for this table afterwards.
+pub fn unmap_page(root: &mut Table, v_addr: usize) -> bool {
+    match leaf_mut(root, v_addr) {
+        Some(leaf) => {
+            if leaf.get_entry() & EntryBits::Owned.val() != 0 {
+                put_page(leaf.get_paddr());
+            }
+            leaf.set_entry(0);
+            true
+        },
+        None => false,
+    }
+}
+
+/// Change the permissions (the R, W, X and U bits) of the leaf that maps
+/// `v_addr` to those in `bits`. A page that is still shared with another
+/// process doesn't get the write bit, but is marked copy-on-write, so it
+/// gets copied before the first store. Returns false if nothing was
+/// mapped there. The caller has to fence the TLB for this table
+/// afterwards.
+pub fn protect_page(root: &mut Table, v_addr: usize, bits: i64) -> bool {
+    let leaf = match leaf_mut(root, v_addr) {
+        Some(leaf) => leaf,
+        None => return false,
+    };
+    let entry = leaf.get_entry();
+    let shared = entry & EntryBits::CopyOnWrite.val() != 0 ||
+        (entry & EntryBits::Owned.val() != 0 && page_refs(leaf.get_paddr()) != 0);
+    let mut bits = bits & 0x1e;
+    if shared && bits & EntryBits::Write.val() != 0 {
+        bits = bits & !EntryBits::Write.val() | EntryBits::CopyOnWrite.val();
+    }
+    leaf.set_entry(entry & !(0x1e | EntryBits::CopyOnWrite.val()) | bits);
+    true
+}
+
+/// Resolve a store to a copy-on-write page at `v_addr`. If others still
+/// share the page, we copy it into a new page of our own and drop our
+/// reference on the old one. If we're the last one holding it, we can
+/// simply make it writable again. Returns false if `v_addr` isn't a
+/// copy-on-write page, which means the store is a real fault.
+/// The caller has to fence the TLB for this table afterwards.
+pub fn resolve_cow(root: &mut Table, v_addr: usize) -> bool {
+    let leaf = match leaf_mut(root, v_addr) {
+        Some(leaf) if leaf.get_entry() & EntryBits::CopyOnWrite.val() != 0 => leaf,
+        _ => return false,
+    };
+    let old = leaf.get_paddr();
+    let bits = leaf.get_entry() & 0x3ff & !EntryBits::CopyOnWrite.val();
+    if page_refs(old) == 0 {
+        leaf.set_entry(leaf.get_entry() & !0x3ff | bits | EntryBits::Write.val());
+        return true;
+    }
+    let new = alloc(1);
+    if new.is_null() {
+        return false;
+    }
+    unsafe {
+        memcpy(new, old as *const u8, PAGE_SIZE);
+    }
+    put_page(old);
+    // The new page is ours alone, so the mapping owns it.
+    leaf.set_entry((new as i64 >> 2) | bits | EntryBits::Write.val() | EntryBits::Owned.val());
+    true
+}
+
 /// Walk the page table to convert a virtual address to a
 /// physical address.
 /// If a page fault would occur, this returns None
 /// Otherwise, it returns Some with the physical address.
 pub fn virt_to_phys(root: &Table, v_addr: usize) -> Option<usize> {
+    virt_to_phys_bits(root, v_addr).map(|(p_addr, _)| p_addr)
+}
+
+/// The same walk as [`virt_to_phys`], but it also hands back the
+/// bits of the leaf entry so that callers can check the U/R/W/X
+/// permissions of the mapping.
+pub fn virt_to_phys_bits(root: &Table, v_addr: usize) -> Option<(usize, i64)> {
     // Walk the page table pointed to by root
     let vpn = [
         // VPN[0] = vaddr[20:12]
@@ -497,7 +716,7 @@ pub fn virt_to_phys(root: &Table, v_addr: usize) -> Option<usize> {
             let off_mask = (1 << (12 + i * 9)) - 1;
             let vaddr_pgoff = v_addr & off_mask;
             let addr = ((v.get_entry() << 2) as usize) & !off_mask;
-            return Some(addr | vaddr_pgoff);
+            return Some((addr | vaddr_pgoff, v.get_entry() & 0x3ff));
         }
         // Set v to the next entry which is pointed to by this
         // entry. However, the address was shifted right by 2 places
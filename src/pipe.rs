@@ -0,0 +1,171 @@
+//! # Pipes
+//! A pipe is a ring buffer in the kernel with a read end and a write end.
+//! Each end is a [`PipeEnd`] in some process' descriptor table, and every
+//! copy of one (through fork or dup) counts as another reader or writer.
+//! Once the last writer is gone, readers see the end of the file, and once
+//! the last reader is gone, writes fail.
+//!
+//! Nobody ever sleeps inside the kernel on a pipe. A process that has to
+//! wait is put on the pipe's wait list and runs its system call again when
+//! something changes, see
+//! [`crate::process::ProcessTable::wake_up`].
+use alloc::{
+    collections::VecDeque,
+    rc::Rc,
+    vec::Vec,
+};
+use core::cell::RefCell;
+
+use crate::{
+    fs::{
+        O_NONBLOCK,
+        O_RDONLY,
+        O_WRONLY,
+    },
+    process::with_table,
+};
+
+/// How many bytes a pipe holds before writers have to wait
+pub const PIPE_SIZE: usize = 4096;
+
+struct Pipe {
+    buffer: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+    /// Processes waiting for the pipe to change, whichever end they're on
+    waiting: Vec<u16>,
+}
+
+impl Pipe {
+    /// Let everyone on the wait list try again. If we can't get the
+    /// process table, they stay on the list for the next time.
+    fn wake_all(&mut self) {
+        with_table(|table| {
+            for pid in self.waiting.drain(..) {
+                table.wake_up(pid);
+            }
+        });
+    }
+}
+
+/// What reading or writing a pipe came to
+pub enum PipeResult {
+    /// This many bytes were moved. Reading 0 bytes is the end of the file.
+    Done(usize),
+    /// Nothing can be done right now, try again once woken up.
+    WouldBlock,
+    /// Writing to a pipe nobody reads from
+    Broken,
+}
+
+/// One end of a pipe, as it sits in a file descriptor
+pub struct PipeEnd {
+    pipe: Rc<RefCell<Pipe>>,
+    write: bool,
+    /// O_RDONLY or O_WRONLY, plus O_NONBLOCK if set by fcntl or pipe2
+    pub flags: usize,
+}
+
+/// Make a new pipe and return its read and write ends.
+pub fn pipe(flags: usize) -> (PipeEnd, PipeEnd) {
+    let pipe = Rc::new(RefCell::new(Pipe {
+        buffer: VecDeque::with_capacity(PIPE_SIZE),
+        readers: 1,
+        writers: 1,
+        waiting: Vec::new(),
+    }));
+    let read = PipeEnd {
+        pipe: pipe.clone(),
+        write: false,
+        flags: O_RDONLY | flags & O_NONBLOCK,
+    };
+    let write = PipeEnd {
+        pipe,
+        write: true,
+        flags: O_WRONLY | flags & O_NONBLOCK,
+    };
+    (read, write)
+}
+
+impl PipeEnd {
+    /// Take up to `buffer.len()` bytes out of the pipe.
+    pub fn read(&self, buffer: &mut [u8]) -> PipeResult {
+        let mut pipe = self.pipe.borrow_mut();
+        if buffer.is_empty() {
+            return PipeResult::Done(0);
+        }
+        if pipe.buffer.is_empty() {
+            return if pipe.writers == 0 {
+                PipeResult::Done(0)
+            } else {
+                PipeResult::WouldBlock
+            };
+        }
+        let count = buffer.len().min(pipe.buffer.len());
+        for (dst, src) in buffer.iter_mut().zip(pipe.buffer.drain(..count)) {
+            *dst = src;
+        }
+        // There is room for the writers again.
+        pipe.wake_all();
+        PipeResult::Done(count)
+    }
+
+    /// Put as much of `buffer` into the pipe as fits.
+    pub fn write(&self, buffer: &[u8]) -> PipeResult {
+        let mut pipe = self.pipe.borrow_mut();
+        if pipe.readers == 0 {
+            return PipeResult::Broken;
+        }
+        let count = buffer.len().min(PIPE_SIZE - pipe.buffer.len());
+        if count == 0 && !buffer.is_empty() {
+            return PipeResult::WouldBlock;
+        }
+        pipe.buffer.extend(&buffer[..count]);
+        pipe.wake_all();
+        PipeResult::Done(count)
+    }
+
+    /// Wake `pid` up the next time something happens to this pipe.
+    pub fn wait(&self, pid: u16) {
+        let mut pipe = self.pipe.borrow_mut();
+        if !pipe.waiting.contains(&pid) {
+            pipe.waiting.push(pid);
+        }
+    }
+}
+
+impl Clone for PipeEnd {
+    fn clone(&self) -> Self {
+        {
+            let mut pipe = self.pipe.borrow_mut();
+            if self.write {
+                pipe.writers += 1;
+            } else {
+                pipe.readers += 1;
+            }
+        }
+        Self {
+            pipe: self.pipe.clone(),
+            write: self.write,
+            flags: self.flags,
+        }
+    }
+}
+
+impl Drop for PipeEnd {
+    /// Closing the last end on one side is news for whoever waits on the
+    /// other side: readers get the end of the file, writers an error.
+    fn drop(&mut self) {
+        let mut pipe = self.pipe.borrow_mut();
+        let left = if self.write {
+            pipe.writers -= 1;
+            pipe.writers
+        } else {
+            pipe.readers -= 1;
+            pipe.readers
+        };
+        if left == 0 {
+            pipe.wake_all();
+        }
+    }
+}
@@ -1,4 +1,6 @@
 use crate::{
+    console,
+    rtc,
     uart::Uart,
     virtio,
 };
@@ -125,27 +127,14 @@ pub fn handle_interrupt() {
                 // just simply wraps 0x1000_0000 (UART).
                 let mut my_uart = Uart::new(0x1000_0000);
                 // If we get here, the UART better have something! If not, what happened??
+                // Echoing and everything else is up to the console.
                 if let Some(c) = my_uart.get() {
-                    // If you recognize this code, it used to be in the lib.rs under kmain(). That
-                    // was because we needed to poll for UART data. Now that we have interrupts,
-                    // here it goes!
-                    match c {
-                        8 => {
-                            // This is a backspace, so we
-                            // essentially have to write a space and
-                            // backup again:
-                            print!("{} {}", 8 as char, 8 as char);
-                        },
-                        10 | 13 => {
-                            // Newline or carriage-return
-                            println!();
-                        },
-                        _ => {
-                            print!("{}", c as char);
-                        },
-                    }
+                    console::receive(c);
                 }
             },
+            rtc::RTC_IRQ => {
+                rtc::handle_interrupt();
+            },
             _ => {
                 println!("Unknown external interrupt: {}", interrupt);
             },
@@ -0,0 +1,66 @@
+//! # Power
+//! QEMU's virt machine has a SiFive test device, the "finisher". Writing
+//! to it ends the emulation, either powering off with an exit status for
+//! QEMU or rebooting. That lets the shell shut us down and the panic
+//! handler tell a test run that it failed.
+//!
+//! Userspace asks through the reboot system call, which wants the same
+//! magic numbers as on Linux so that nothing stumbles into it.
+
+/// The SiFive test device
+pub const FINISHER_BASE: usize = 0x10_0000;
+
+// What to write to the finisher. FAIL carries an exit code in the top 16
+// bits, which QEMU exits with as (code << 1) | 1.
+const FINISHER_FAIL: u32 = 0x3333;
+const FINISHER_PASS: u32 = 0x5555;
+const FINISHER_RESET: u32 = 0x7777;
+
+// The magic numbers for reboot, the same as Linux uses. Any of the
+// second ones will do.
+pub const LINUX_REBOOT_MAGIC1: u32 = 0xfee1_dead;
+pub const LINUX_REBOOT_MAGIC2: u32 = 0x2812_1969;
+pub const LINUX_REBOOT_MAGIC2A: u32 = 0x0512_1996;
+pub const LINUX_REBOOT_MAGIC2B: u32 = 0x1604_1998;
+pub const LINUX_REBOOT_MAGIC2C: u32 = 0x2011_2000;
+
+// Commands for reboot. Like the magic numbers, these are 32 bits, so
+// that an int from C compares right.
+pub const LINUX_REBOOT_CMD_RESTART: u32 = 0x0123_4567;
+pub const LINUX_REBOOT_CMD_HALT: u32 = 0xcdef_0123;
+pub const LINUX_REBOOT_CMD_CAD_ON: u32 = 0x89ab_cdef;
+pub const LINUX_REBOOT_CMD_CAD_OFF: u32 = 0x0000_0000;
+pub const LINUX_REBOOT_CMD_POWER_OFF: u32 = 0x4321_fedc;
+pub const LINUX_REBOOT_CMD_RESTART2: u32 = 0xa1b2_c3d4;
+
+/// Whether `magic1` and `magic2` are what reboot wants
+pub const fn magic_ok(magic1: u32, magic2: u32) -> bool {
+    magic1 == LINUX_REBOOT_MAGIC1 &&
+        matches!(
+            magic2,
+            LINUX_REBOOT_MAGIC2 | LINUX_REBOOT_MAGIC2A | LINUX_REBOOT_MAGIC2B | LINUX_REBOOT_MAGIC2C
+        )
+}
+
+fn finish(value: u32) -> ! {
+    unsafe {
+        (FINISHER_BASE as *mut u32).write_volatile(value);
+    }
+    // Without the test device, all we can do is stop.
+    crate::abort();
+}
+
+/// Switch the machine off, and have QEMU exit with 0.
+pub fn poweroff() -> ! {
+    finish(FINISHER_PASS)
+}
+
+/// Switch the machine off, and have QEMU exit with a failure.
+pub fn fail(code: u16) -> ! {
+    finish(FINISHER_FAIL | (code as u32) << 16)
+}
+
+/// Start over from the very beginning.
+pub fn reboot() -> ! {
+    finish(FINISHER_RESET)
+}
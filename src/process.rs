@@ -1,16 +1,28 @@
 use alloc::{
+    boxed::Box,
     collections::{
-        vec_deque::VecDeque,
         BTreeMap,
+        BTreeSet,
     },
+    format,
+    rc::Rc,
     string::String,
+    vec,
+    vec::Vec,
+};
+use core::{
+    cell::RefCell,
+    convert::TryFrom,
+    mem,
+    ptr::null_mut,
 };
-use core::ptr::null_mut;
 
 use crate::{
+    console,
     cpu::{
         build_satp,
         get_mtime,
+        memcpy,
         satp_fence_asid,
         CpuMode,
         Registers,
@@ -18,168 +30,372 @@ use crate::{
         TrapFrame,
     },
     fs::Inode,
-    lock::Mutex,
+    pipe::PipeEnd,
     page::{
+        align_val,
         alloc,
+        allocation_size,
         dealloc,
+        for_each_leaf,
+        get_page,
         map,
+        put_page,
+        protect_page,
+        split,
         unmap,
+        resolve_cow,
+        unmap_page,
+        virt_to_phys_bits,
         zalloc,
         EntryBits,
         Table,
         PAGE_SIZE,
     },
-    syscall::syscall_exit,
+    sched::RunQueue,
+    signal::Signals,
+    strace::Trace,
+    syscall::{
+        syscall_exit,
+        syscall_wait4,
+    },
+    time::Times,
+    vma::{
+        Backing,
+        Vma,
+        VmaList,
+        MMAP_BASE,
+        PROT_READ,
+        PROT_WRITE,
+    },
 };
 
 // How many pages are we going to give a process for their
 // stack?
 pub const STACK_PAGES: usize = 16;
+// Only the first STACK_PAGES are mapped up front. Below them, the stack
+// grows a page at a time as it is touched, up to this many pages.
+pub const STACK_MAX_PAGES: usize = 256;
 // We want to adjust the stack to be at the bottom of the memory allocation
 // regardless of where it is on the kernel heap.
 pub const STACK_ADDR: usize = 0x1_0000_0000;
 // All processes will have a defined starting point in virtual memory.
 // We will use this later when we load processes from disk.
 pub const PROCESS_STARTING_ADDR: usize = 0x2000_0000;
+// Auxiliary vector entries we put on the stack of a new program, see
+// set_args. Each one is a pair of the type and its value.
+const AT_NULL: usize = 0;
+const AT_PAGESZ: usize = 6;
+const AT_ENTRY: usize = 9;
+
+/// How many processes there can be at once. Pids go from 1 to one below
+/// this, and since a pid is also the ASID of its process, this has to
+/// stay within the 16 bits satp has for it.
+pub const PID_MAX: usize = 4096;
+/// How many descriptors a process can have open. Descriptors go from 0
+/// to one below this.
+pub const OPEN_MAX: usize = 1024;
+// The init process is the first one we create. It adopts every
+// process whose parent exits first.
+pub const INIT_PID: u16 = 1;
+// Option for wait4 to return 0 instead of blocking if no child has
+// exited yet.
+pub const WNOHANG: usize = 1;
 
-// Here, we store a process list. It uses the global allocator
-// that we made before and its job is to store all processes.
-// We will have this list OWN the process. So, anytime we want
-// the process, we will consult the process list.
-// Using an Option here is one method of creating a "lazy static".
-// Rust requires that all statics be initialized, but all
-// initializations must be at compile-time. We cannot allocate
-// a VecDeque at compile time, so we are somewhat forced to
-// do this.
-pub static mut PROCESS_LIST: Option<VecDeque<Process>> = None;
-pub static mut PROCESS_LIST_MUTEX: Mutex = Mutex::new();
-// We can search through the process list to get a new PID, but
-// it's probably easier and faster just to increase the pid:
-pub static mut NEXT_PID: u16 = 1;
-
-// The following set_* and get_by_pid functions are C-style functions
-// They probably need to be re-written in a more Rusty style, but for
-// now they are how we control processes by PID.
-
-/// Set a process' state to running. This doesn't do any checks.
-/// If this PID is not found, this returns false. Otherwise, it
-/// returns true.
-pub fn set_running(pid: u16) -> bool {
-    // Yes, this is O(n). A better idea here would be a static list
-    // of process pointers.
-    let mut retval = false;
+/// Every process there is, indexed by pid, along with the queue of those
+/// that are ready to run. The table owns the processes, so anytime we
+/// want one, we consult the table. Each process is boxed so that it stays
+/// where it is while others come and go, which [`get_by_pid`] relies on.
+pub struct ProcessTable {
+    slots: Vec<Option<Box<Process>>>,
+    /// The pid we handed out last. New pids are looked for after it, so
+    /// that a pid isn't used again right after it was freed.
+    last_pid: u16,
+    /// Exactly the processes that are Running
+    run_queue: RunQueue,
+    /// Sleeping processes by the mtime they wake up at. One that is woken
+    /// up early stays in here until then, see
+    /// [`ProcessTable::wake_sleepers`].
+    sleepers: BTreeSet<(usize, u16)>,
+}
+
+// Rust requires that all statics be initialized at compile time, but we
+// cannot allocate the table then, so it's an Option that init() fills
+// in. Taking the table out of it is also how we keep it to ourselves:
+// anyone else who tries to get it meanwhile finds None.
+static mut PROCESS_TABLE: Option<ProcessTable> = None;
+
+/// Run `f` on the process table. Returns None if somebody else has the
+/// table, which happens when we're in the trap handler and a kernel
+/// process was busy with it. Kernel processes never have to wait for one
+/// another: while one of them holds the table, the scheduler can't take
+/// it and has to let it go on.
+///
+/// `f` must not drop anything that needs the table in turn, such as the
+/// last end of a pipe somebody is waiting on. Take those out and drop
+/// them once `with_table` returns.
+pub fn with_table<R>(f: impl FnOnce(&mut ProcessTable) -> R) -> Option<R> {
     unsafe {
-        if let Some(mut pl) = PROCESS_LIST.take() {
-            for proc in &mut pl {
-                if proc.pid == pid {
-                    proc.set_state(ProcessState::Running);
-                    retval = true;
-                    break;
-                }
-            }
-            // Now, we no longer need the owned Deque, so we hand it
-            // back by replacing the PROCESS_LIST's None with the
-            // Some(pl).
-            PROCESS_LIST.replace(pl);
-        }
+        let mut table = PROCESS_TABLE.take()?;
+        let ret = f(&mut table);
+        PROCESS_TABLE.replace(table);
+        Some(ret)
     }
-    retval
 }
 
-/// Set a process' state to waiting. This doesn't do any checks.
-/// If this PID is not found, this returns false. Otherwise, it
-/// returns true.
-pub fn set_waiting(pid: u16) -> bool {
-    // Yes, this is O(n). A better idea here would be a static list
-    // of process pointers.
-    let mut retval = false;
-    unsafe {
-        if let Some(mut pl) = PROCESS_LIST.take() {
-            for proc in pl.iter_mut() {
-                if proc.pid == pid {
-                    proc.set_state(ProcessState::Waiting);
-                    retval = true;
-                    break;
-                }
-            }
-            // Now, we no longer need the owned Deque, so we hand it
-            // back by replacing the PROCESS_LIST's None with the
-            // Some(pl).
-            PROCESS_LIST.replace(pl);
+impl ProcessTable {
+    fn new() -> Self {
+        let mut slots = Vec::with_capacity(PID_MAX);
+        slots.resize_with(PID_MAX, || None);
+        Self {
+            slots,
+            last_pid: 0,
+            run_queue: RunQueue::new(),
+            sleepers: BTreeSet::new(),
         }
     }
-    retval
-}
 
-/// Sleep a process
-pub fn set_sleeping(pid: u16, duration: usize) -> bool {
-    // Yes, this is O(n). A better idea here would be a static list
-    // of process pointers.
-    let mut retval = false;
-    unsafe {
-        if let Some(mut pl) = PROCESS_LIST.take() {
-            for proc in &mut pl {
-                if proc.pid == pid {
-                    proc.set_state(ProcessState::Sleeping);
-                    proc.set_sleep_until(get_mtime() + duration);
-                    retval = true;
-                    break;
-                }
+    pub fn get(&self, pid: u16) -> Option<&Process> {
+        self.slots.get(pid as usize)?.as_deref()
+    }
+
+    pub fn get_mut(&mut self, pid: u16) -> Option<&mut Process> {
+        self.slots.get_mut(pid as usize)?.as_deref_mut()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Process> {
+        self.slots.iter().filter_map(|p| p.as_deref())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Process> {
+        self.slots.iter_mut().filter_map(|p| p.as_deref_mut())
+    }
+
+    /// The first free pid after the last one we handed out. A pid is free
+    /// once no process has it, not even a zombie, and no process group is
+    /// named after it anymore.
+    fn alloc_pid(&self) -> Option<u16> {
+        let mut pid = self.last_pid as usize;
+        for _ in 1..PID_MAX {
+            pid = if pid + 1 == PID_MAX { 1 } else { pid + 1 };
+            if self.slots[pid].is_none() && !self.group_exists(pid as u16) {
+                return Some(pid as u16);
             }
-            // Now, we no longer need the owned Deque, so we hand it
-            // back by replacing the PROCESS_LIST's None with the
-            // Some(pl).
-            PROCESS_LIST.replace(pl);
         }
+        None
     }
-    retval
-}
 
-/// Delete a process given by pid. If this process doesn't exist,
-/// this function does nothing.
-pub fn delete_process(pid: u16) {
-    unsafe {
-        if let Some(mut pl) = PROCESS_LIST.take() {
-            for i in 0..pl.len() {
-                let p = pl.get_mut(i).unwrap();
-                if p.get_pid() == pid {
-                    // When the structure gets dropped, all
-                    // of the allocations get deallocated.
-                    pl.remove(i);
-                    break;
-                }
-            }
-            // Now, we no longer need the owned Deque, so we hand it
-            // back by replacing the PROCESS_LIST's None with the
-            // Some(pl).
-            PROCESS_LIST.replace(pl);
+    /// Add the process that `make` makes for a new pid, ready to run, and
+    /// return that pid. Returns None if we're out of pids, or if `make`
+    /// gives up.
+    pub fn add(&mut self, make: impl FnOnce(u16) -> Option<Process>) -> Option<u16> {
+        let pid = self.alloc_pid()?;
+        let process = make(pid)?;
+        self.last_pid = pid;
+        // The pid may have been somebody else's ASID before.
+        satp_fence_asid(pid as usize);
+        self.run_queue.add(pid, process.nice);
+        self.slots[pid as usize] = Some(Box::new(process));
+        Some(pid)
+    }
+
+    /// Take a process out of the table. When the box gets dropped, all of
+    /// the process' allocations get deallocated.
+    pub fn remove(&mut self, pid: u16) -> Option<Box<Process>> {
+        let process = self.slots.get_mut(pid as usize)?.take()?;
+        self.run_queue.remove(pid);
+        Some(process)
+    }
+
+    /// Set a process' state. This doesn't do any checks, but keeps the run
+    /// queue in step. Returns false if there is no such process.
+    pub fn set_state(&mut self, pid: u16, state: ProcessState) -> bool {
+        let running = matches!(state, ProcessState::Running);
+        let nice = match self.get_mut(pid) {
+            Some(p) => {
+                p.set_state(state);
+                p.nice
+            },
+            None => return false,
+        };
+        if running {
+            self.run_queue.push(pid, nice);
+        } else {
+            self.run_queue.remove(pid);
         }
+        true
     }
-}
 
-/// Get a process by PID. Since we leak the process list, this is
-/// unsafe since the process can be deleted and we'll still have a pointer.
-pub unsafe fn get_by_pid(pid: u16) -> *mut Process {
-    let mut ret = null_mut();
-    if let Some(mut pl) = PROCESS_LIST.take() {
-        for i in &mut pl {
-            if i.get_pid() == pid {
-                ret = i as *mut Process;
+    /// Change how nice a process is, and with that its priority. Returns
+    /// false if there is no such process.
+    pub fn set_nice(&mut self, pid: u16, nice: i8) -> bool {
+        match self.get_mut(pid) {
+            Some(p) => p.nice = nice,
+            None => return false,
+        }
+        self.run_queue.set_nice(pid, nice);
+        true
+    }
+
+    /// A process ran for `ticks` of mtime, from being switched in to the
+    /// next trap.
+    pub fn charge(&mut self, pid: u16, ticks: u64) {
+        if let Some(nice) = self.get(pid).map(|p| p.nice) {
+            self.run_queue.charge(pid, nice, ticks);
+        }
+    }
+
+    /// How many context switch times a process gets to run once it's
+    /// switched in
+    pub fn time_slice(&self, pid: u16) -> Option<u16> {
+        let nice = self.get(pid)?.nice;
+        Some(self.run_queue.time_slice(nice))
+    }
+
+    /// Put a process to sleep for `duration` mtime ticks.
+    pub fn sleep(&mut self, pid: u16, duration: usize) -> bool {
+        let until = get_mtime() + duration;
+        if !self.set_state(pid, ProcessState::Sleeping) {
+            return false;
+        }
+        self.get_mut(pid).unwrap().set_sleep_until(until);
+        self.sleepers.insert((until, pid));
+        true
+    }
+
+    /// Wake up the processes whose sleep is over at mtime `now`.
+    pub fn wake_sleepers(&mut self, now: usize) {
+        while let Some(&(until, pid)) = self.sleepers.iter().next() {
+            if until > now {
                 break;
             }
+            self.sleepers.remove(&(until, pid));
+            // A signal may have woken it up already, and it may have gone
+            // on to do something else by now.
+            let asleep = self.get(pid).map_or(false, |p| {
+                matches!(p.state, ProcessState::Sleeping) && p.sleep_until == until
+            });
+            if asleep {
+                self.set_state(pid, ProcessState::Running);
+            }
+        }
+    }
+
+    /// Wake up a process waiting in a system call that it runs again, see
+    /// [`Process::interruptible`]. Anything else is left alone, since it
+    /// might have exited or be waiting on something else by now.
+    pub fn wake_up(&mut self, pid: u16) -> bool {
+        match self.get_mut(pid) {
+            Some(p) if p.interruptible && matches!(p.state, ProcessState::Waiting) => {
+                p.interruptible = false;
+            },
+            _ => return false,
+        }
+        self.set_state(pid, ProcessState::Running)
+    }
+
+    /// Whether any process is in process group `pgid`
+    pub fn group_exists(&self, pgid: u16) -> bool {
+        self.iter()
+            .any(|p| p.pgid == pgid && !matches!(p.state, ProcessState::Dead))
+    }
+
+    /// Look for an exited child of `ppid`, the way wait4 picks them: if
+    /// `pid` is positive, only that child counts, and -1 is any child. 0
+    /// is any child in our own process group, and below -1, any child in
+    /// process group -`pid`.
+    pub fn find_child(&self, ppid: u16, pid: isize) -> ChildStatus {
+        let pgid = match pid {
+            0 => self.get(ppid).map(|p| p.pgid),
+            pid if pid < -1 => pid.checked_neg().and_then(|pgid| u16::try_from(pgid).ok()),
+            _ => None,
+        };
+        let mut ret = ChildStatus::NoChildren;
+        for p in self.iter() {
+            let wanted = match pid {
+                -1 => true,
+                pid if pid > 0 => p.pid as isize == pid,
+                _ => pgid == Some(p.pgid),
+            };
+            if p.ppid != ppid || !wanted {
+                continue;
+            }
+            if let ProcessState::Dead = p.state {
+                return ChildStatus::Exited(p.pid, p.exit_status);
+            }
+            ret = ChildStatus::Running;
+        }
+        ret
+    }
+
+    /// Terminate a process with `status` in the format wait4 hands out.
+    /// Its children are handed to init, and the process stays around as a
+    /// zombie until its parent collects the status. If its parent is the
+    /// kernel, nobody will, so it's removed right away.
+    ///
+    /// Returns the files it had open. Closing a pipe wakes up whoever
+    /// waits on the other end, so drop them once the table is back.
+    #[must_use]
+    pub fn exit(&mut self, pid: u16, status: usize) -> BTreeMap<u16, FileDescriptor> {
+        let (ppid, files) = match self.get_mut(pid) {
+            Some(p) => {
+                p.exit_status = status;
+                // Nobody can use our files anymore.
+                (p.ppid, p.data.take_fds())
+            },
+            None => return BTreeMap::new(),
+        };
+        self.set_state(pid, ProcessState::Dead);
+        for p in self.iter_mut().filter(|p| p.ppid == pid) {
+            p.ppid = INIT_PID;
+        }
+        if ppid == 0 || self.get(ppid).is_none() {
+            self.remove(pid);
+        } else {
+            // A parent blocked in wait4 retries the call when it runs
+            // again, and will find us there.
+            self.wake_up(ppid);
         }
-        PROCESS_LIST.replace(pl);
+        files
+    }
+
+    /// Whose turn it is to run, if anybody's
+    pub fn pick_next(&mut self) -> Option<&Process> {
+        let pid = self.run_queue.pick()?;
+        self.get(pid)
     }
-    ret
+}
+
+/// What wait4 found among the children of a process.
+pub enum ChildStatus {
+    /// This child has exited with the given status and can be reaped.
+    Exited(u16, usize),
+    /// There are matching children, but all of them are still alive.
+    Running,
+    /// There aren't any children matching the request.
+    NoChildren,
+}
+
+/// Get a process by PID. The table keeps every process boxed, so the
+/// pointer stays good for as long as the process is in the table. This
+/// is unsafe since the process can be removed and we'll still have a
+/// pointer. Returns null if there is no such process, or if we can't
+/// get the table right now.
+pub unsafe fn get_by_pid(pid: u16) -> *mut Process {
+    with_table(|table| table.get_mut(pid).map(|p| p as *mut Process))
+        .flatten()
+        .unwrap_or(null_mut())
 }
 
 /// We will eventually move this function out of here, but its
-/// job is just to take a slot in the process list.
+/// job is just to take a slot in the process table.
 fn init_process() {
     // We can't do much here until we have system calls because
     // we're running in User space.
     println!("Init process started...");
     loop {
+        // We adopt every orphan, so we're the one who has to collect
+        // their exit status once they're done.
+        while syscall_wait4(-1, null_mut(), WNOHANG) as isize > 0 {}
         // Alright, I forgot. We cannot put init to sleep since the
         // scheduler will loop until it finds a process to run. Since
         // the scheduler is called in an interrupt context, nothing else
@@ -198,111 +414,21 @@ fn init_process() {
     }
 }
 
-/// Add a process given a function address and then
-/// push it onto the `LinkedList`. Uses `Process::new_default`
+/// Add a process given a function address. Uses `Process::new_default`
 /// to create a new stack, etc.
 pub fn add_process_default(pr: fn()) {
-    unsafe {
-        // This is the Rust-ism that really trips up C++ programmers.
-        // PROCESS_LIST is wrapped in an Option<> enumeration, which
-        // means that the Option owns the Deque. We can only borrow from
-        // it or move ownership to us. In this case, we choose the
-        // latter, where we move ownership to us, add a process, and
-        // then move ownership back to the PROCESS_LIST.
-        // This allows mutual exclusion as anyone else trying to grab
-        // the process list will get None rather than the Deque.
-        PROCESS_LIST_MUTEX.spin_lock();
-        if let Some(mut pl) = PROCESS_LIST.take() {
-            // .take() will replace PROCESS_LIST with None and give
-            // us the only copy of the Deque.
-            let p = Process::new_default(pr);
-            pl.push_back(p);
-            // Now, we no longer need the owned Deque, so we hand it
-            // back by replacing the PROCESS_LIST's None with the
-            // Some(pl).
-            PROCESS_LIST.replace(pl);
-        }
-        PROCESS_LIST_MUTEX.unlock();
-        // TODO: When we get to multi-hart processing, we need to keep
-        // trying to grab the process list. We can do this with an
-        // atomic instruction. but right now, we're a single-processor
-        // computer.
-    }
+    // TODO: When we get to multi-hart processing, we need to keep
+    // trying to grab the process table. We can do this with an
+    // atomic instruction. but right now, we're a single-processor
+    // computer.
+    with_table(|table| table.add(|pid| Some(Process::new_default(pid, pr))));
 }
 
-/// Add a kernel process.
+/// Add a kernel process. Returns its pid, or 0 if it couldn't be added.
 pub fn add_kernel_process(func: fn()) -> u16 {
-    // This is the Rust-ism that really trips up C++ programmers.
-    // PROCESS_LIST is wrapped in an Option<> enumeration, which
-    // means that the Option owns the Deque. We can only borrow from
-    // it or move ownership to us. In this case, we choose the
-    // latter, where we move ownership to us, add a process, and
-    // then move ownership back to the PROCESS_LIST.
-    // This allows mutual exclusion as anyone else trying to grab
-    // the process list will get None rather than the Deque.
-    // .take() will replace PROCESS_LIST with None and give
-    // us the only copy of the Deque.
-    let func_addr = func as usize;
-    let func_v_addr = func_addr; //- 0x6000_0000;
-    // println!("func_addr = {:x} -> {:x}", func_addr, func_vaddr);
-    // We will convert NEXT_PID below into an atomic increment when
-    // we start getting into multi-hart processing. For now, we want
-    // a process. Get it to work, then improve it!
-    let my_pid = unsafe { NEXT_PID };
-    let mut ret_proc = Process {
-        frame: zalloc(1) as *mut TrapFrame,
-        stack: zalloc(STACK_PAGES),
-        pid: my_pid,
-        root: zalloc(1) as *mut Table,
-        state: ProcessState::Running,
-        data: ProcessData::new(),
-        sleep_until: 0,
-        program: null_mut(),
-    };
-    unsafe {
-        NEXT_PID += 1;
-    }
-    // Now we move the stack pointer to the bottom of the
-    // allocation. The spec shows that register x2 (2) is the stack
-    // pointer.
-    // We could use ret_proc.stack.add, but that's an unsafe
-    // function which would require an unsafe block. So, convert it
-    // to usize first and then add PAGE_SIZE is better.
-    // We also need to set the stack adjustment so that it is at the
-    // bottom of the memory and far away from heap allocations.
-    unsafe {
-        (*ret_proc.frame).pc = func_v_addr;
-        // 1 is the return address register. This makes it so we
-        // don't have to do syscall_exit() when a kernel process
-        // finishes.
-        (*ret_proc.frame).regs[Registers::Ra as usize] = ra_delete_proc as usize;
-        (*ret_proc.frame).regs[Registers::Sp as usize] = ret_proc.stack as usize + STACK_PAGES * 4096;
-        (*ret_proc.frame).mode = CpuMode::Machine as usize;
-        (*ret_proc.frame).pid = ret_proc.pid as usize;
-    }
-
-    unsafe { PROCESS_LIST.take() }.map_or_else(
-        || {
-            unsafe {
-                PROCESS_LIST_MUTEX.unlock();
-            }
-            // TODO: When we get to multi-hart processing, we need to keep
-            // trying to grab the process list. We can do this with an
-            // atomic instruction. but right now, we're a single-processor
-            // computer.
-            0
-        },
-        |mut pl| {
-            pl.push_back(ret_proc);
-            // Now, we no longer need the owned Deque, so we hand it
-            // back by replacing the PROCESS_LIST's None with the
-            // Some(pl).
-            unsafe {
-                PROCESS_LIST.replace(pl);
-            }
-            my_pid
-        },
-    )
+    with_table(|table| table.add(|pid| Some(Process::new_kernel(pid, func as usize, 0))))
+        .flatten()
+        .unwrap_or(0)
 }
 
 /// A kernel process is just a function inside of the kernel. Each
@@ -318,78 +444,9 @@ fn ra_delete_proc() {
 /// arguments. Typically, this will be a memory address on the heap where
 /// arguments can be found.
 pub fn add_kernel_process_args(func: fn(args_ptr: usize), args: usize) -> u16 {
-    // This is the Rust-ism that really trips up C++ programmers.
-    // PROCESS_LIST is wrapped in an Option<> enumeration, which
-    // means that the Option owns the Deque. We can only borrow from
-    // it or move ownership to us. In this case, we choose the
-    // latter, where we move ownership to us, add a process, and
-    // then move ownership back to the PROCESS_LIST.
-    // This allows mutual exclusion as anyone else trying to grab
-    // the process list will get None rather than the Deque.
-    unsafe {
-        PROCESS_LIST_MUTEX.spin_lock();
-    }
-    if let Some(mut pl) = unsafe { PROCESS_LIST.take() } {
-        // .take() will replace PROCESS_LIST with None and give
-        // us the only copy of the Deque.
-        let func_addr = func as usize;
-        let func_v_addr = func_addr; //- 0x6000_0000;
-        // println!("func_addr = {:x} -> {:x}", func_addr, func_vaddr);
-        // We will convert NEXT_PID below into an atomic increment when
-        // we start getting into multi-hart processing. For now, we want
-        // a process. Get it to work, then improve it!
-        let my_pid = unsafe { NEXT_PID };
-        let mut ret_proc = Process {
-            frame: zalloc(1) as *mut TrapFrame,
-            stack: zalloc(STACK_PAGES),
-            pid: my_pid,
-            root: zalloc(1) as *mut Table,
-            state: ProcessState::Running,
-            data: ProcessData::new(),
-            sleep_until: 0,
-            program: null_mut(),
-        };
-        unsafe {
-            NEXT_PID += 1;
-        }
-        // Now we move the stack pointer to the bottom of the
-        // allocation. The spec shows that register x2 (2) is the stack
-        // pointer.
-        // We could use ret_proc.stack.add, but that's an unsafe
-        // function which would require an unsafe block. So, convert it
-        // to usize first and then add PAGE_SIZE is better.
-        // We also need to set the stack adjustment so that it is at the
-        // bottom of the memory and far away from heap allocations.
-        unsafe {
-            (*ret_proc.frame).pc = func_v_addr;
-            (*ret_proc.frame).regs[Registers::A0 as usize] = args;
-            // 1 is the return address register. This makes it so we
-            // don't have to do syscall_exit() when a kernel process
-            // finishes.
-            (*ret_proc.frame).regs[Registers::Ra as usize] = ra_delete_proc as usize;
-            (*ret_proc.frame).regs[Registers::Sp as usize] = ret_proc.stack as usize + STACK_PAGES * 4096;
-            (*ret_proc.frame).mode = CpuMode::Machine as usize;
-            (*ret_proc.frame).pid = ret_proc.pid as usize;
-        }
-        pl.push_back(ret_proc);
-        // Now, we no longer need the owned Deque, so we hand it
-        // back by replacing the PROCESS_LIST's None with the
-        // Some(pl).
-        unsafe {
-            PROCESS_LIST.replace(pl);
-            PROCESS_LIST_MUTEX.unlock();
-        }
-        my_pid
-    } else {
-        unsafe {
-            PROCESS_LIST_MUTEX.unlock();
-        }
-        // TODO: When we get to multi-hart processing, we need to keep
-        // trying to grab the process list. We can do this with an
-        // atomic instruction. but right now, we're a single-processor
-        // computer.
-        0
-    }
+    with_table(|table| table.add(|pid| Some(Process::new_kernel(pid, func as usize, args))))
+        .flatten()
+        .unwrap_or(0)
 }
 
 /// This should only be called once, and its job is to create
@@ -397,34 +454,19 @@ pub fn add_kernel_process_args(func: fn(args_ptr: usize), args: usize) -> u16 {
 /// but later, it should call the shell.
 pub fn init() -> usize {
     unsafe {
-        PROCESS_LIST_MUTEX.spin_lock();
-        PROCESS_LIST = Some(VecDeque::with_capacity(15));
-        // add_process_default(init_process);
-        add_kernel_process(init_process);
-        // Ugh....Rust is giving me fits over here!
-        // I just want a memory address to the trap frame, but
-        // due to the borrow rules of Rust, I'm fighting here. So,
-        // instead, let's move the value out of PROCESS_LIST, get
-        // the address, and then move it right back in.
-        let pl = PROCESS_LIST.take().unwrap();
-        let p = pl.front().unwrap().frame;
-        // let frame = p as *const TrapFrame as usize;
-        // println!("Init's frame is at 0x{:08x}", frame);
-        // Put the process list back in the global.
-        PROCESS_LIST.replace(pl);
-        PROCESS_LIST_MUTEX.unlock();
-        // Return the first instruction's address to execute.
-        // Since we use the MMU, all start here.
-        (*p).pc
+        PROCESS_TABLE = Some(ProcessTable::new());
     }
+    let pid = add_kernel_process(init_process);
+    // Return the first instruction's address to execute.
+    unsafe { (*(*get_by_pid(pid)).frame).pc }
 }
 
 // Our process must be able to sleep, wait, or run.
 // Running - means that when the scheduler finds this process, it can run it.
 // Sleeping - means that the process is waiting on a certain amount of time.
 // Waiting - means that the process is waiting on I/O
-// Dead - We should never get here, but we can flag a process as Dead and clean
-//        it out of the list later.
+// Dead - The process has exited, but its parent hasn't collected the exit
+//        status with wait4 yet (a zombie). It is cleaned out of the list then.
 pub enum ProcessState {
     Running,
     Sleeping,
@@ -437,10 +479,40 @@ pub struct Process {
     pub stack: *mut u8,
     pub pid: u16,
     pub root: *mut Table,
+    // Only the process table changes this, so that the run queue stays
+    // in step, see ProcessTable::set_state.
     pub state: ProcessState,
     pub data: ProcessData,
     pub sleep_until: usize,
     pub program: *mut u8,
+    // The process that created us, or 0 if the kernel did. This is
+    // who gets our exit status.
+    pub ppid: u16,
+    // Our process group. Kill can signal a whole group at once, and the
+    // console signals its foreground group when ^C is typed.
+    pub pgid: u16,
+    // Exit status in the format wait4 hands out, valid once we're Dead.
+    pub exit_status: usize,
+    // Set while we're Waiting in a system call that simply runs again
+    // once we wake up, such as wait4 or reading an empty pipe, rather
+    // than on a kernel process doing I/O for us. Waking us up early is
+    // harmless then, so exiting children and signals do.
+    pub interruptible: bool,
+    // The heap of a user program starts right after its last segment
+    // and ends at the program break, which brk moves around.
+    pub brk_start: usize,
+    pub brk: usize,
+    // Every area of our address space: the program, its heap and stack,
+    // and everything mapped with mmap and friends
+    pub vmas: VmaList,
+    pub signals: Signals,
+    // Set if our system calls get logged, see strace
+    pub trace: Option<Trace>,
+    pub times: Times,
+    // How nice we are to others, which decides our priority or weight,
+    // see sched. Only change it through ProcessTable::set_nice, so that
+    // the run queue hears about it.
+    pub nice: i8,
 }
 
 // Most of this operating system runs more of a C-style, where
@@ -481,7 +553,7 @@ impl Process {
         &self.state
     }
 
-    pub fn set_state(&mut self, ps: ProcessState) {
+    fn set_state(&mut self, ps: ProcessState) {
         self.state = ps;
     }
 
@@ -497,27 +569,31 @@ impl Process {
         self.sleep_until = until;
     }
 
-    pub fn new_default(func: fn()) -> Self {
+    pub fn new_default(pid: u16, func: fn()) -> Self {
         let func_addr = func as usize;
         let func_v_addr = func_addr;
         // println!("func_addr = {:x} -> {:x}", func_addr, func_vaddr);
-        // We will convert NEXT_PID below into an atomic increment when
-        // we start getting into multi-hart processing. For now, we want
-        // a process. Get it to work, then improve it!
         let mut ret_proc = Self {
             frame: zalloc(1) as *mut TrapFrame,
             stack: alloc(STACK_PAGES),
-            pid: unsafe { NEXT_PID },
+            pid,
             root: zalloc(1) as *mut Table,
             state: ProcessState::Running,
             data: ProcessData::new(),
             sleep_until: 0,
             program: null_mut(),
+            ppid: 0,
+            pgid: pid,
+            exit_status: 0,
+            interruptible: false,
+            brk_start: 0,
+            brk: 0,
+            vmas: VmaList::new(),
+            signals: Signals::new(),
+            trace: None,
+            times: Times::default(),
+            nice: 0,
         };
-        unsafe {
-            satp_fence_asid(NEXT_PID as usize);
-            NEXT_PID += 1;
-        }
         // Now we move the stack pointer to the bottom of the
         // allocation. The spec shows that register x2 (2) is the stack
         // pointer.
@@ -561,14 +637,393 @@ impl Process {
         }
         ret_proc
     }
+
+    /// A kernel process that runs the function at `func` with `args` in
+    /// A0. It runs in machine mode on its own stack, without a page table
+    /// of its own.
+    fn new_kernel(pid: u16, func: usize, args: usize) -> Self {
+        let ret_proc = Self {
+            frame: zalloc(1) as *mut TrapFrame,
+            stack: zalloc(STACK_PAGES),
+            pid,
+            root: zalloc(1) as *mut Table,
+            state: ProcessState::Running,
+            data: ProcessData::new(),
+            sleep_until: 0,
+            program: null_mut(),
+            ppid: 0,
+            pgid: pid,
+            exit_status: 0,
+            interruptible: false,
+            brk_start: 0,
+            brk: 0,
+            vmas: VmaList::new(),
+            signals: Signals::new(),
+            trace: None,
+            times: Times::default(),
+            nice: 0,
+        };
+        // Now we move the stack pointer to the bottom of the
+        // allocation. The spec shows that register x2 (2) is the stack
+        // pointer.
+        // We could use ret_proc.stack.add, but that's an unsafe
+        // function which would require an unsafe block. So, convert it
+        // to usize first and then add PAGE_SIZE is better.
+        // We also need to set the stack adjustment so that it is at the
+        // bottom of the memory and far away from heap allocations.
+        unsafe {
+            (*ret_proc.frame).pc = func;
+            (*ret_proc.frame).regs[Registers::A0 as usize] = args;
+            // 1 is the return address register. This makes it so we
+            // don't have to do syscall_exit() when a kernel process
+            // finishes.
+            (*ret_proc.frame).regs[Registers::Ra as usize] = ra_delete_proc as usize;
+            (*ret_proc.frame).regs[Registers::Sp as usize] = ret_proc.stack as usize + STACK_PAGES * 4096;
+            (*ret_proc.frame).mode = CpuMode::Machine as usize;
+            (*ret_proc.frame).pid = ret_proc.pid as usize;
+        }
+        ret_proc
+    }
+
+    /// Make a copy of this process for fork(). The child gets `pid`,
+    /// a copy of our trap frame with A0 = 0, and a new page table that
+    /// maps the same pages at the same virtual addresses. Writable pages
+    /// are shared copy-on-write: both of us lose the write bit, and
+    /// whoever stores to one first gets its own copy in
+    /// `page::resolve_cow`. Anything we don't own, such as a framebuffer, is
+    /// simply shared. Kernel processes use their stack by physical
+    /// address, so they can't be forked and we return None.
+    pub fn fork(&mut self, pid: u16) -> Option<Self> {
+        if unsafe { (*self.frame).mode } != CpuMode::User as usize {
+            return None;
+        }
+        // Our stack and program pages are single allocations that we free
+        // in one go. From now on every page can be held by both of us, so
+        // break them up and let each mapping hold its own page instead.
+        // The ELF loader may map a page twice or not at all, so every
+        // extra mapping takes a reference and unmapped pages are freed.
+        let program_pages = if self.program.is_null() {
+            0
+        } else {
+            allocation_size(self.program)
+        };
+        let stack = self.stack as usize..self.stack as usize + STACK_PAGES * PAGE_SIZE;
+        let program = self.program as usize..self.program as usize + program_pages * PAGE_SIZE;
+        let mut mapped = vec![false; STACK_PAGES + program_pages];
+        let parent_table = unsafe { &mut *self.root };
+        for_each_leaf(parent_table, &mut |_, entry| {
+            let p_addr = entry.get_paddr();
+            let idx = if stack.contains(&p_addr) {
+                (p_addr - stack.start) / PAGE_SIZE
+            } else if program.contains(&p_addr) {
+                STACK_PAGES + (p_addr - program.start) / PAGE_SIZE
+            } else {
+                return;
+            };
+            if mapped[idx] {
+                get_page(p_addr);
+            }
+            mapped[idx] = true;
+            entry.set_entry(entry.get_entry() | EntryBits::Owned.val());
+        });
+        split(self.stack);
+        if program_pages != 0 {
+            split(self.program);
+        }
+        for (idx, _) in mapped.iter().enumerate().filter(|(_, &m)| !m) {
+            if idx < STACK_PAGES {
+                put_page(stack.start + idx * PAGE_SIZE);
+            } else {
+                put_page(program.start + (idx - STACK_PAGES) * PAGE_SIZE);
+            }
+        }
+        self.stack = null_mut();
+        self.program = null_mut();
+
+        let ret_proc = Self {
+            frame: zalloc(1) as *mut TrapFrame,
+            stack: null_mut(),
+            pid,
+            root: zalloc(1) as *mut Table,
+            state: ProcessState::Running,
+            data: self.data.clone(),
+            sleep_until: 0,
+            program: null_mut(),
+            ppid: self.pid,
+            pgid: self.pgid,
+            exit_status: 0,
+            interruptible: false,
+            brk_start: self.brk_start,
+            brk: self.brk,
+            vmas: self.vmas.clone(),
+            signals: self.signals.fork(),
+            trace: self.trace.as_ref().and_then(Trace::fork),
+            times: Times::default(),
+            nice: self.nice,
+        };
+        let table = unsafe { &mut *ret_proc.root };
+        for_each_leaf(parent_table, &mut |v_addr, entry| {
+            let mut bits = entry.get_entry() & 0x3ff;
+            if bits & EntryBits::Owned.val() != 0 {
+                if bits & EntryBits::Write.val() != 0 {
+                    bits = bits & !EntryBits::Write.val() | EntryBits::CopyOnWrite.val();
+                    entry.set_entry(entry.get_entry() & !0x3ff | bits);
+                }
+                get_page(entry.get_paddr());
+            }
+            // Only hand map the permission bits, it adds V, A and D itself.
+            map(table, v_addr, entry.get_paddr(), bits & 0x33e, 0);
+        });
+        unsafe {
+            *ret_proc.frame = *self.frame;
+            // The child returns 0 from fork, right after the ecall.
+            (*ret_proc.frame).regs[Registers::A0 as usize] = 0;
+            (*ret_proc.frame).pc += 4;
+            (*ret_proc.frame).pid = pid as usize;
+            (*ret_proc.frame).satp = build_satp(SatpMode::Sv39, pid as usize, ret_proc.root as usize);
+        }
+        // We just took away write permissions from ourselves.
+        satp_fence_asid(self.pid as usize);
+        satp_fence_asid(pid as usize);
+        Some(ret_proc)
+    }
+
+    /// Move the program break to `brk` and return where it ends up. The
+    /// heap is an anonymous area, so new heap pages are only zeroed and
+    /// mapped once they're touched, and pages above a lowered break are
+    /// unmapped and freed. If the break would run into something that is
+    /// already mapped, it stays where it was, which is what brk returns
+    /// to say it failed. brk(0) just asks where the break is. The heap
+    /// has to stay below where mmap puts things.
+    pub fn set_brk(&mut self, brk: usize) -> usize {
+        if self.brk_start == 0 || brk < self.brk_start || brk > MMAP_BASE {
+            return self.brk;
+        }
+        let old_end = align_val(self.brk, 12);
+        let new_end = align_val(brk, 12);
+        if new_end > old_end {
+            if !self.vmas.is_free(old_end, new_end) {
+                return self.brk;
+            }
+            self.vmas.insert(Vma {
+                start: old_end,
+                end: new_end,
+                prot: PROT_READ | PROT_WRITE,
+                backing: Backing::Anonymous,
+            });
+        } else if new_end < old_end {
+            self.unmap_area(new_end, old_end);
+        }
+        self.brk = brk;
+        brk
+    }
+
+    /// Map a new area, which has to be free in [`VmaList`]. Anonymous
+    /// areas are only added to the list, their pages come in through
+    /// [`Process::handle_fault`]. File-backed areas get zeroed pages of
+    /// their own right away, which the caller fills in, and device areas
+    /// map the device's memory directly. Returns false if we ran out of
+    /// memory, with nothing mapped.
+    pub fn map_area(&mut self, vma: Vma) -> bool {
+        if let Backing::Anonymous = vma.backing {
+            self.vmas.insert(vma);
+            return true;
+        }
+        let table = unsafe { &mut *self.root };
+        for v_addr in (vma.start..vma.end).step_by(PAGE_SIZE) {
+            let (p_addr, bits) = match vma.backing {
+                Backing::Device(p_addr) => (p_addr + v_addr - vma.start, vma.entry_bits()),
+                _ => (zalloc(1) as usize, vma.entry_bits() | EntryBits::Owned.val()),
+            };
+            if p_addr == 0 {
+                for addr in (vma.start..v_addr).step_by(PAGE_SIZE) {
+                    unmap_page(table, addr);
+                }
+                return false;
+            }
+            map(table, v_addr, p_addr, bits, 0);
+        }
+        self.vmas.insert(vma);
+        true
+    }
+
+    /// Unmap whatever areas there are in `[start, end)`, splitting those
+    /// that stick out at either end.
+    pub fn unmap_area(&mut self, start: usize, end: usize) {
+        let table = unsafe { &mut *self.root };
+        self.vmas.remove(start, end, &mut |vma| {
+            for v_addr in (vma.start..vma.end).step_by(PAGE_SIZE) {
+                unmap_page(table, v_addr);
+            }
+        });
+        satp_fence_asid(self.pid as usize);
+    }
+
+    /// Change the protection of `[start, end)` to `prot`. All of it has to
+    /// be mapped, and file mappings can't be made writable, since we
+    /// can't write the file back.
+    pub fn protect_area(&mut self, start: usize, end: usize, prot: usize) -> bool {
+        if !self.vmas.is_covered(start, end) {
+            return false;
+        }
+        if prot & PROT_WRITE != 0 &&
+            self.vmas
+                .iter()
+                .any(|vma| vma.start < end && start < vma.end && matches!(vma.backing, Backing::File { .. }))
+        {
+            return false;
+        }
+        let table = unsafe { &mut *self.root };
+        self.vmas.protect(start, end, prot, &mut |vma| {
+            for v_addr in (vma.start..vma.end).step_by(PAGE_SIZE) {
+                protect_page(table, v_addr, vma.entry_bits());
+            }
+        });
+        satp_fence_asid(self.pid as usize);
+        true
+    }
+
+    /// Handle a page fault at `addr` for an access that needs `prot`
+    /// (one of PROT_READ, PROT_WRITE or PROT_EXEC). A fault is fine if it
+    /// lands in one of our areas that allows the access, and then we
+    /// either map a zeroed page there for the first time or break a
+    /// copy-on-write share. Right below the stack, the stack grows into
+    /// a new area. Returns false if the fault is a real error, which
+    /// should get the process killed.
+    pub fn handle_fault(&mut self, addr: usize, prot: usize) -> bool {
+        let page = addr & !(PAGE_SIZE - 1);
+        if self.vmas.find(addr).is_none() && !self.grow_stack(page) {
+            return false;
+        }
+        let vma = *self.vmas.find(addr).unwrap();
+        // Writable areas can be read as well, see Vma::entry_bits.
+        let allowed = if vma.prot & PROT_WRITE != 0 {
+            vma.prot | PROT_READ
+        } else {
+            vma.prot
+        };
+        if allowed & prot != prot {
+            return false;
+        }
+        let table = unsafe { &mut *self.root };
+        let resolved = match virt_to_phys_bits(table, page) {
+            Some((_, bits)) => {
+                prot == PROT_WRITE && bits & EntryBits::CopyOnWrite.val() != 0 && resolve_cow(table, page)
+            },
+            None => match vma.backing {
+                Backing::Anonymous => {
+                    let p_addr = zalloc(1) as usize;
+                    if p_addr != 0 {
+                        map(table, page, p_addr, vma.entry_bits() | EntryBits::Owned.val(), 0);
+                    }
+                    p_addr != 0
+                },
+                Backing::Device(p_addr) => {
+                    map(table, page, p_addr + page - vma.start, vma.entry_bits(), 0);
+                    true
+                },
+                // These are read in when they're mapped, so there is
+                // nothing to be filled in later.
+                Backing::File { .. } => false,
+            },
+        };
+        if resolved {
+            satp_fence_asid(self.pid as usize);
+        }
+        resolved
+    }
+
+    /// Extend the stack down to `page`, if that is within
+    /// [`STACK_MAX_PAGES`] of its top and nothing else is in the way.
+    fn grow_stack(&mut self, page: usize) -> bool {
+        let top = STACK_ADDR + STACK_PAGES * PAGE_SIZE;
+        let bottom = match self.vmas.find(top - 1) {
+            Some(vma) => vma.start,
+            None => return false,
+        };
+        if page >= bottom || page < top - STACK_MAX_PAGES * PAGE_SIZE || !self.vmas.is_free(page, bottom) {
+            return false;
+        }
+        self.vmas.insert(Vma {
+            start: page,
+            end: bottom,
+            prot: PROT_READ | PROT_WRITE,
+            backing: Backing::Anonymous,
+        });
+        true
+    }
+
+    /// Lay out argv and envp at the top of the stack of a process fresh
+    /// out of `elf::File::load_proc`, the way the RISC-V SysV ABI wants
+    /// them: argc at the stack pointer, followed by the argv pointers, a
+    /// NULL, the envp pointers, a NULL and the auxiliary vector. The
+    /// strings themselves go above all of that. We also hand argc, argv
+    /// and envp over in A0 to A2, so that main() can be called directly.
+    /// Returns false if they take up more than half of the stack.
+    pub fn set_args(&mut self, argv: &[String], envp: &[String]) -> bool {
+        let top = STACK_ADDR + STACK_PAGES * PAGE_SIZE;
+        let strings: usize = argv.iter().chain(envp).map(|s| s.len() + 1).sum();
+        let mut words = Vec::with_capacity(argv.len() + envp.len() + 9);
+        words.push(argv.len());
+        // The stack is a single allocation mapped at STACK_ADDR, so every
+        // address on it is the same offset into the allocation.
+        let stack = self.stack as usize;
+        let phys = |v_addr: usize| (stack + v_addr - STACK_ADDR) as *mut u8;
+        let mut str_addr = top - strings;
+        if str_addr < top - STACK_PAGES * PAGE_SIZE / 2 {
+            return false;
+        }
+        for list in &[argv, envp] {
+            for s in list.iter() {
+                unsafe {
+                    memcpy(phys(str_addr), s.as_ptr(), s.len());
+                    *phys(str_addr + s.len()) = 0;
+                }
+                words.push(str_addr);
+                str_addr += s.len() + 1;
+            }
+            words.push(0);
+        }
+        let entry = unsafe { (*self.frame).pc };
+        words.extend_from_slice(&[AT_PAGESZ, PAGE_SIZE, AT_ENTRY, entry, AT_NULL, 0]);
+        // The ABI wants the stack pointer 16-byte aligned.
+        let sp = (top - strings - words.len() * 8) & !15;
+        if sp < top - STACK_PAGES * PAGE_SIZE / 2 {
+            return false;
+        }
+        for (i, word) in words.iter().enumerate() {
+            unsafe {
+                *(phys(sp + i * 8) as *mut usize) = *word;
+            }
+        }
+        unsafe {
+            (*self.frame).regs[Registers::Sp as usize] = sp;
+            (*self.frame).regs[Registers::A0 as usize] = argv.len();
+            (*self.frame).regs[Registers::A1 as usize] = sp + 8;
+            (*self.frame).regs[Registers::A2 as usize] = sp + 8 * (argv.len() + 2);
+        }
+        self.data.environ.clear();
+        for var in envp {
+            if let Some((key, value)) = var.split_once('=') {
+                self.data.environ.insert(String::from(key), String::from(value));
+            }
+        }
+        true
+    }
 }
 
 impl Drop for Process {
-    /// Since we're storing ownership of a Process in the linked list,
+    /// Since we're storing ownership of a Process in the process table,
     /// we can cause it to deallocate automatically when it is removed.
     fn drop(&mut self) {
-        // We allocate the stack as a page.
-        dealloc(self.stack);
+        // We allocate the stack as a page. After a fork, the stack pages
+        // are owned by the page table instead.
+        if !self.stack.is_null() {
+            dealloc(self.stack);
+        }
+        // Give back everything mmap handed out while the page table that
+        // maps it is still there.
+        self.unmap_area(0, usize::MAX & !(PAGE_SIZE - 1));
         // This is unsafe, but it's at the drop stage, so we won't
         // be using this again.
         unsafe {
@@ -585,8 +1040,29 @@ impl Drop for Process {
     }
 }
 
+/// A file opened by `openat`. Descriptors that dup or fork make share
+/// it, offset and flags and all, but two opens of the same path can be
+/// read independently.
+pub struct OpenFile {
+    /// Block device the inode lives on
+    pub bdev: usize,
+    /// Number of the inode on the device
+    pub ino: u32,
+    /// Copy of the inode taken at open time
+    pub inode: Inode,
+    /// Byte offset the next read will start from
+    pub offset: usize,
+    /// Flags given to `openat` (O_RDONLY and friends)
+    pub flags: usize,
+    /// The absolute path it was opened by, so that openat can find
+    /// paths relative to a directory
+    pub path: String,
+}
+
+#[derive(Clone)]
 pub enum FileDescriptor {
-    File(Inode),
+    File(Rc<RefCell<OpenFile>>),
+    Pipe(PipeEnd),
     Device(usize),
     Network,
     Unknown,
@@ -598,20 +1074,107 @@ pub enum FileDescriptor {
 // We will allow dead code for now until we have a need for the
 // private process data. This is essentially our resource control block (RCB).
 #[allow(dead_code)]
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct ProcessData {
     environ: BTreeMap<String, String>,
-    fdesc: BTreeMap<u16, FileDescriptor>,
+    pub fdesc: BTreeMap<u16, FileDescriptor>,
+    // Descriptors to close when we execv (FD_CLOEXEC)
+    cloexec: BTreeSet<u16>,
+    // The absolute path relative paths start from
+    cwd: String,
 }
 
 // This is private data that we can query with system calls.
 // If we want to implement CFQ (completely fair queuing), which
 // is a per-process block queuing algorithm, we can put that here.
 impl ProcessData {
+    /// Data for a new process, which starts out in the root directory
+    /// with the console as its standard input, output and error.
     pub fn new() -> Self {
-        // ProcessData {
-        //     ..Default::default()
-        // }
-        Self::default()
+        let mut data = Self {
+            cwd: String::from("/"),
+            ..Self::default()
+        };
+        for fd in 0..3 {
+            data.set_fd(fd, FileDescriptor::Device(console::CONSOLE));
+        }
+        data
+    }
+
+    pub fn cwd(&self) -> &str {
+        &self.cwd
+    }
+
+    /// Change the current directory to `path`, which has to be absolute
+    /// and normalized already, see [`crate::fs::resolve_path`].
+    pub fn set_cwd(&mut self, path: String) {
+        self.cwd = path;
+    }
+
+    /// The environment as a list of "KEY=VALUE" strings, the way a
+    /// program gets it on its stack.
+    pub fn environ_strings(&self) -> Vec<String> {
+        self.environ
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect()
+    }
+
+    /// Store a descriptor under the lowest free number, like POSIX asks
+    /// us to, and return that number. Returns None if all [`OPEN_MAX`]
+    /// are taken.
+    pub fn add_fd(&mut self, desc: FileDescriptor) -> Option<u16> {
+        self.add_fd_from(desc, 0)
+    }
+
+    /// Store a descriptor under the lowest free number that is at least
+    /// `min`, which is what fcntl(F_DUPFD) wants. Returns None if there
+    /// is no such number below [`OPEN_MAX`].
+    pub fn add_fd_from(&mut self, desc: FileDescriptor, min: u16) -> Option<u16> {
+        let mut fd = min;
+        while self.fdesc.contains_key(&fd) {
+            fd = fd.checked_add(1)?;
+        }
+        if fd as usize >= OPEN_MAX {
+            return None;
+        }
+        self.fdesc.insert(fd, desc);
+        Some(fd)
+    }
+
+    /// Put a descriptor at `fd`, closing whatever was there before.
+    pub fn set_fd(&mut self, fd: u16, desc: FileDescriptor) {
+        self.cloexec.remove(&fd);
+        self.fdesc.insert(fd, desc);
+    }
+
+    /// Take `fd` out of the table. Dropping what comes back closes it.
+    pub fn remove_fd(&mut self, fd: u16) -> Option<FileDescriptor> {
+        self.cloexec.remove(&fd);
+        self.fdesc.remove(&fd)
+    }
+
+    /// Take all of our descriptors.
+    pub fn take_fds(&mut self) -> BTreeMap<u16, FileDescriptor> {
+        self.cloexec.clear();
+        mem::take(&mut self.fdesc)
+    }
+
+    /// Take the descriptors that a new program shouldn't get.
+    pub fn take_cloexec_fds(&mut self) -> Vec<FileDescriptor> {
+        let fds = mem::take(&mut self.cloexec);
+        fds.iter().filter_map(|fd| self.fdesc.remove(fd)).collect()
+    }
+
+    pub fn is_cloexec(&self, fd: u16) -> bool {
+        self.cloexec.contains(&fd)
+    }
+
+    pub fn set_cloexec(&mut self, fd: u16, cloexec: bool) {
+        if cloexec {
+            self.cloexec.insert(fd);
+        } else {
+            self.cloexec.remove(&fd);
+        }
     }
 }
@@ -0,0 +1,60 @@
+//! # Real-time clock
+//! QEMU's virt machine has a Goldfish RTC, which knows the date as
+//! nanoseconds since the epoch. We only read it once at boot and work out
+//! how far the epoch is from mtime. From then on the realtime clock goes
+//! by mtime like every other clock, see [`crate::time::realtime_ns`].
+//!
+//! The RTC can also raise an alarm on its interrupt, but we have no use
+//! for that and keep it switched off.
+use crate::time;
+
+pub const RTC_BASE: usize = 0x10_1000;
+/// The RTC's interrupt on the PLIC
+pub const RTC_IRQ: u32 = 11;
+
+// Registers, as offsets from RTC_BASE. All of them are 32 bits wide.
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
+const IRQ_ENABLED: usize = 0x10;
+const CLEAR_ALARM: usize = 0x14;
+const CLEAR_INTERRUPT: usize = 0x1c;
+
+unsafe fn read_reg(reg: usize) -> u32 {
+    ((RTC_BASE + reg) as *const u32).read_volatile()
+}
+
+unsafe fn write_reg(reg: usize, value: u32) {
+    ((RTC_BASE + reg) as *mut u32).write_volatile(value);
+}
+
+/// Nanoseconds since the epoch. Reading the low half latches the high
+/// half, so they have to be read in this order.
+pub fn read() -> u64 {
+    unsafe {
+        let low = read_reg(TIME_LOW) as u64;
+        let high = read_reg(TIME_HIGH) as u64;
+        high << 32 | low
+    }
+}
+
+/// Switch the alarm off, and set the realtime clock to the date.
+pub fn init() {
+    unsafe {
+        write_reg(IRQ_ENABLED, 0);
+        write_reg(CLEAR_ALARM, 0);
+        write_reg(CLEAR_INTERRUPT, 0);
+    }
+    let now = read();
+    // A clock that says 1970 is no better than the time since boot.
+    if now != 0 {
+        time::set_realtime(now);
+    }
+}
+
+/// Only an alarm interrupts, and we never set one. Whatever it was,
+/// it's done with.
+pub fn handle_interrupt() {
+    unsafe {
+        write_reg(CLEAR_INTERRUPT, 0);
+    }
+}
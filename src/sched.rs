@@ -1,50 +1,332 @@
+//! # Scheduler
+//! Every process has a nice value from [`NICE_MIN`] to [`NICE_MAX`], which
+//! it inherits on fork and keeps across execv. There are two ways to
+//! schedule by it, and which one we use is settled at boot, see
+//! [`RunQueue::new`].
+//!
+//! By priority, the default, a process' priority is 20 minus its nice
+//! value, from 1 to 40, and the runnable process with the highest
+//! priority goes next. So that nobody starves, processes age: every time
+//! one is passed over, it counts as one priority higher, until it gets
+//! its turn. Among processes that come out even, the one that waited
+//! longest wins, which makes processes of the same priority take turns.
+//! Higher priorities also get longer time slices, see [`time_slice`].
+//!
+//! The fair scheduler works like Linux's CFS instead. Every process has a
+//! virtual runtime: the mtime it actually ran, from being switched in to
+//! the next trap, scaled down by its weight. The runnable process that is
+//! furthest behind goes next, so over time everybody gets a share of the
+//! CPU that goes with their weight.
+use alloc::{
+    collections::{
+        BTreeSet,
+        VecDeque,
+    },
+    vec,
+    vec::Vec,
+};
+
 use crate::{
-    cpu::get_mtime,
+    cmdline,
+    cpu::{
+        get_mtime,
+        CONTEXT_SWITCH_TIME,
+    },
     process::{
-        ProcessState,
-        PROCESS_LIST,
-        PROCESS_LIST_MUTEX,
+        with_table,
+        PID_MAX,
     },
 };
 
-pub fn schedule() -> usize {
-    let mut frame_addr: usize = 0x1111;
-    unsafe {
-        // If we can't get the lock, then usually this means a kernel
-        // process has the lock. So, we return 0. This has a special
-        // meaning to whomever calls the scheduler to say "nobody else got scheduled"
-        if !PROCESS_LIST_MUTEX.try_lock() {
-            return 0;
-        }
-        if let Some(mut pl) = PROCESS_LIST.take() {
-            // Rust allows us to label loops so that break statements can be
-            // targeted.
-            'procfindloop: loop {
-                pl.rotate_left(1);
-                if let Some(prc) = pl.front_mut() {
-                    match prc.get_state() {
-                        ProcessState::Running => {
-                            frame_addr = prc.get_frame_address();
-                            break 'procfindloop;
-                        },
-                        ProcessState::Sleeping => {
-                            // Awaken sleeping processes whose sleep until is in
-                            // the past.
-                            if prc.get_sleep_until() <= get_mtime() {
-                                prc.set_state(ProcessState::Running);
-                                frame_addr = prc.get_frame_address();
-                                break 'procfindloop;
-                            }
-                        },
-                        _ => {},
-                    }
-                }
-            }
-            PROCESS_LIST.replace(pl);
+// Nice values, the same as Linux has them
+pub const NICE_MIN: i8 = -20;
+pub const NICE_MAX: i8 = 19;
+
+// What setpriority and getpriority apply to
+pub const PRIO_PROCESS: usize = 0;
+pub const PRIO_PGRP: usize = 1;
+pub const PRIO_USER: usize = 2;
+
+/// The priority of a process with `nice`, from 1 to 40. Higher runs
+/// first. This is also what getpriority returns.
+pub const fn priority(nice: i8) -> usize {
+    (20 - nice as isize) as usize
+}
+
+/// How many context switch times a process with `nice` gets to run when
+/// we schedule by priority: 10 for the highest priority, 5 for nice 0,
+/// and 1 for the lowest.
+pub const fn time_slice(nice: i8) -> u16 {
+    ((priority(nice) + 3) / 4) as u16
+}
+
+/// The weight of a process with `nice` in the fair scheduler. This is the
+/// table Linux has, where every step in nice is about 10% of the CPU.
+const WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904, 3906, 3121, 2501,
+    1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87, 70, 56, 45, 36, 29, 23, 18, 15,
+];
+/// The weight of nice 0. A process with that runs at virtual time the
+/// same as at real time.
+const NICE_0_WEIGHT: u64 = 1024;
+/// How far behind everybody else a process that slept may start out, so
+/// that it gets to run soon without making up for all of its sleep.
+const SLEEPER_CREDIT: u64 = 3 * CONTEXT_SWITCH_TIME;
+
+const fn weight(nice: i8) -> u64 {
+    WEIGHTS[(nice - NICE_MIN) as usize]
+}
+
+/// The processes that are ready to run, in whichever way we schedule
+pub enum RunQueue {
+    Priority(PriorityQueue),
+    Fair(FairQueue),
+}
+
+impl RunQueue {
+    /// We schedule by priority, unless the kernel was built with the
+    /// `cfs` feature. Either way, `sched=cfs` or `sched=priority` on the
+    /// command line has the last word.
+    pub fn new() -> Self {
+        let fair = match cmdline::value("sched") {
+            Some("cfs") => true,
+            Some("priority") => false,
+            Some(other) => {
+                println!("Unknown scheduler {}, keeping the default", other);
+                cfg!(feature = "cfs")
+            },
+            None => cfg!(feature = "cfs"),
+        };
+        if fair {
+            Self::Fair(FairQueue::new())
         } else {
-            println!("could not take process list");
+            Self::Priority(PriorityQueue::new())
+        }
+    }
+
+    /// `pid` is a new process, ready to run with `nice`.
+    pub fn add(&mut self, pid: u16, nice: i8) {
+        match self {
+            Self::Priority(queue) => queue.push(pid, nice),
+            Self::Fair(queue) => queue.add(pid),
+        }
+    }
+
+    /// `pid` is ready to run with `nice`. Nothing happens if it already
+    /// was.
+    pub fn push(&mut self, pid: u16, nice: i8) {
+        match self {
+            Self::Priority(queue) => queue.push(pid, nice),
+            Self::Fair(queue) => queue.push(pid),
         }
-        PROCESS_LIST_MUTEX.unlock();
     }
-    frame_addr
+
+    /// `pid` isn't ready to run anymore.
+    pub fn remove(&mut self, pid: u16) {
+        match self {
+            Self::Priority(queue) => queue.remove(pid),
+            Self::Fair(queue) => queue.remove(pid),
+        }
+    }
+
+    /// `pid` is now as nice as `nice`. The fair scheduler only needs to
+    /// know when `pid` is charged.
+    pub fn set_nice(&mut self, pid: u16, nice: i8) {
+        if let Self::Priority(queue) = self {
+            queue.set_nice(pid, nice);
+        }
+    }
+
+    /// `pid`, which is as nice as `nice`, ran for `ticks` of mtime.
+    pub fn charge(&mut self, pid: u16, nice: i8, ticks: u64) {
+        if let Self::Fair(queue) = self {
+            queue.charge(pid, nice, ticks);
+        }
+    }
+
+    /// Whose turn it is
+    pub fn pick(&mut self) -> Option<u16> {
+        match self {
+            Self::Priority(queue) => queue.pick(),
+            Self::Fair(queue) => queue.pick(),
+        }
+    }
+
+    /// How many context switch times a process with `nice` gets. When we
+    /// schedule fairly, the share comes from the weights alone, and a
+    /// short slice just means that we check who is behind more often.
+    pub const fn time_slice(&self, nice: i8) -> u16 {
+        match self {
+            Self::Priority(_) => time_slice(nice),
+            Self::Fair(_) => 1,
+        }
+    }
+}
+
+/// A process that is ready to run
+struct Entry {
+    pid: u16,
+    priority: usize,
+    /// How many times it was passed over since it last ran
+    waited: usize,
+}
+
+/// The processes that are ready to run when we schedule by priority
+pub struct PriorityQueue {
+    /// In the order they last ran, so that the first of those who come out
+    /// even has waited longest
+    queue: VecDeque<Entry>,
+    /// Whether a pid is in the queue, so that we don't have to look
+    queued: Vec<bool>,
+}
+
+impl PriorityQueue {
+    fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            queued: vec![false; PID_MAX],
+        }
+    }
+
+    fn push(&mut self, pid: u16, nice: i8) {
+        if !self.queued[pid as usize] {
+            self.queued[pid as usize] = true;
+            self.queue.push_back(Entry {
+                pid,
+                priority: priority(nice),
+                waited: 0,
+            });
+        }
+    }
+
+    fn remove(&mut self, pid: u16) {
+        if self.queued[pid as usize] {
+            self.queued[pid as usize] = false;
+            self.queue.retain(|e| e.pid != pid);
+        }
+    }
+
+    fn set_nice(&mut self, pid: u16, nice: i8) {
+        if let Some(entry) = self.queue.iter_mut().find(|e| e.pid == pid) {
+            entry.priority = priority(nice);
+        }
+    }
+
+    /// Everybody who isn't picked has waited once more.
+    fn pick(&mut self) -> Option<u16> {
+        let mut best = 0;
+        for (i, entry) in self.queue.iter().enumerate() {
+            let best_entry = &self.queue[best];
+            if entry.priority + entry.waited > best_entry.priority + best_entry.waited {
+                best = i;
+            }
+        }
+        let mut entry = self.queue.remove(best)?;
+        for other in self.queue.iter_mut() {
+            other.waited += 1;
+        }
+        entry.waited = 0;
+        let pid = entry.pid;
+        self.queue.push_back(entry);
+        Some(pid)
+    }
+}
+
+/// The processes that are ready to run when we schedule fairly. Virtual
+/// runtimes are in mtime ticks, as if the process had nice 0.
+pub struct FairQueue {
+    /// Runnable processes by virtual runtime, with the pid to tell those
+    /// that are level apart
+    tree: BTreeSet<(u64, u16)>,
+    /// The virtual runtime of every pid, whether it's runnable or not
+    vruntime: Vec<u64>,
+    /// Whether a pid is in the tree
+    queued: Vec<bool>,
+    /// About the least virtual runtime of the runnable processes, which
+    /// new processes and those that wake up are measured against. Unlike
+    /// that, it never goes back.
+    min_vruntime: u64,
+}
+
+impl FairQueue {
+    fn new() -> Self {
+        Self {
+            tree: BTreeSet::new(),
+            vruntime: vec![0; PID_MAX],
+            queued: vec![false; PID_MAX],
+            min_vruntime: 0,
+        }
+    }
+
+    fn insert(&mut self, pid: u16) {
+        self.queued[pid as usize] = true;
+        self.tree.insert((self.vruntime[pid as usize], pid));
+    }
+
+    /// A new process starts out level with whoever is furthest behind,
+    /// so that it neither has to wait for everybody nor gets to run for
+    /// as long as the others have.
+    fn add(&mut self, pid: u16) {
+        self.remove(pid);
+        self.vruntime[pid as usize] = self.min_vruntime;
+        self.insert(pid);
+    }
+
+    /// A process that wakes up keeps its virtual runtime if it's still
+    /// ahead. Otherwise, it only gets a little credit for its sleep, or it
+    /// would have the CPU to itself until it caught up.
+    fn push(&mut self, pid: u16) {
+        if !self.queued[pid as usize] {
+            let vruntime = &mut self.vruntime[pid as usize];
+            *vruntime = (*vruntime).max(self.min_vruntime.saturating_sub(SLEEPER_CREDIT));
+            self.insert(pid);
+        }
+    }
+
+    fn remove(&mut self, pid: u16) {
+        if self.queued[pid as usize] {
+            self.queued[pid as usize] = false;
+            self.tree.remove(&(self.vruntime[pid as usize], pid));
+        }
+    }
+
+    /// The more weight a process has, the slower its virtual runtime goes.
+    fn charge(&mut self, pid: u16, nice: i8, ticks: u64) {
+        let queued = self.queued[pid as usize];
+        self.remove(pid);
+        self.vruntime[pid as usize] += ticks * NICE_0_WEIGHT / weight(nice);
+        if queued {
+            self.insert(pid);
+        }
+        self.update_min();
+    }
+
+    fn update_min(&mut self) {
+        if let Some(&(vruntime, _)) = self.tree.iter().next() {
+            self.min_vruntime = self.min_vruntime.max(vruntime);
+        }
+    }
+
+    /// Whoever is furthest behind. It stays in the tree while it runs,
+    /// and moves along as it's charged.
+    fn pick(&mut self) -> Option<u16> {
+        self.update_min();
+        self.tree.iter().next().map(|&(_, pid)| pid)
+    }
+}
+
+/// Pick the next process to run and return the address of its trap frame.
+/// Returns 0 if a kernel process has the process table, which has a special
+/// meaning to whomever calls the scheduler to say "nobody else got scheduled".
+pub fn schedule() -> usize {
+    with_table(|table| {
+        loop {
+            // Awaken sleeping processes whose sleep until is in the past.
+            table.wake_sleepers(get_mtime());
+            if let Some(prc) = table.pick_next() {
+                break prc.get_frame_address();
+            }
+        }
+    })
+    .unwrap_or(0)
 }
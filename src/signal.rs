@@ -0,0 +1,361 @@
+//! # Signals
+//! Every process has a set of pending signals, a set of blocked ones and
+//! an action for each signal. Signals are only ever acted on right before
+//! a process goes back to user mode, see [`deliver`]. A signal with a
+//! handler gets a [`SignalFrame`] pushed onto the user stack, holding the
+//! trap frame the process was interrupted with, and the handler returns
+//! through sigreturn, which puts that trap frame back.
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use crate::{
+    cpu::{
+        CpuMode,
+        Registers,
+        TrapFrame,
+    },
+    process::{
+        get_by_pid,
+        with_table,
+        ProcessState,
+        ProcessTable,
+    },
+    uaccess::{
+        read_user,
+        write_user,
+    },
+};
+
+// Signal numbers, the same as Linux uses
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGURG: usize = 23;
+pub const SIGWINCH: usize = 28;
+/// Signals go from 1 up to and including this one.
+pub const NSIG: usize = 64;
+
+// Special handlers
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+// Flags for rt_sigaction
+pub const SA_RESTORER: usize = 0x0400_0000;
+pub const SA_NODEFER: usize = 0x4000_0000;
+pub const SA_RESETHAND: usize = 0x8000_0000;
+
+// What rt_sigprocmask does with the set it is given
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+/// A handler returns to this address unless it brings its own restorer
+/// (SA_RESTORER). Nothing is ever mapped here, so the instruction page
+/// fault it causes tells the trap handler to do a sigreturn.
+pub const SIGRETURN_ADDR: usize = 0x3f_ffff_f000;
+
+/// The mask bit of `sig`
+pub const fn sigmask(sig: usize) -> u64 {
+    1 << (sig - 1)
+}
+
+/// These can't be caught, blocked or ignored.
+const UNBLOCKABLE: u64 = sigmask(SIGKILL) | sigmask(SIGSTOP);
+
+/// What rt_sigaction reads and writes. Since there is no vDSO to return
+/// through, the layout is the one that has room for a restorer.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct SigAction {
+    /// SIG_DFL, SIG_IGN or the address of the handler
+    pub handler: usize,
+    /// SA_* flags
+    pub flags: usize,
+    /// Where the handler returns to if SA_RESTORER is set. This should
+    /// make the rt_sigreturn system call.
+    pub restorer: usize,
+    /// More signals to block while the handler runs
+    pub mask: u64,
+}
+
+/// What we push onto the user stack before running a handler. The
+/// handler gets a pointer to it in A2.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SignalFrame {
+    /// The registers the process had when the signal came in
+    pub regs: [usize; 32],
+    pub fregs: [usize; 32],
+    pub pc: usize,
+    /// The blocked signals to go back to
+    pub blocked: u64,
+    pub sig: usize,
+}
+
+/// The signal state of one process
+#[derive(Clone)]
+pub struct Signals {
+    pub pending: u64,
+    pub blocked: u64,
+    /// The action for signal n is at n - 1.
+    pub actions: [SigAction; NSIG],
+}
+
+impl Signals {
+    pub fn new() -> Self {
+        Self {
+            pending: 0,
+            blocked: 0,
+            actions: [SigAction::default(); NSIG],
+        }
+    }
+
+    /// What a child gets on fork: the same actions and blocked signals,
+    /// but none of our pending ones.
+    pub fn fork(&self) -> Self {
+        Self {
+            pending: 0,
+            ..self.clone()
+        }
+    }
+
+    /// A new program keeps pending, blocked and ignored signals, but our
+    /// handlers are gone with the old program.
+    pub fn exec(&mut self) {
+        for action in self.actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SigAction::default();
+            }
+        }
+    }
+
+    pub fn action(&self, sig: usize) -> &SigAction {
+        &self.actions[sig - 1]
+    }
+
+    /// Set the action for `sig`, unless it's one that can't be caught.
+    pub fn set_action(&mut self, sig: usize, action: SigAction) -> bool {
+        if !is_valid(sig) || sigmask(sig) & UNBLOCKABLE != 0 {
+            return false;
+        }
+        self.actions[sig - 1] = action;
+        true
+    }
+
+    pub fn set_blocked(&mut self, blocked: u64) {
+        self.blocked = blocked & !UNBLOCKABLE;
+    }
+
+    /// Whether getting `sig` would make no difference at all
+    fn ignores(&self, sig: usize) -> bool {
+        match self.action(sig).handler {
+            SIG_IGN => true,
+            SIG_DFL => ignored_by_default(sig),
+            _ => false,
+        }
+    }
+
+    /// Take the lowest pending signal that isn't blocked.
+    fn take_next(&mut self) -> Option<usize> {
+        let ready = self.pending & !self.blocked;
+        if ready == 0 {
+            return None;
+        }
+        let sig = ready.trailing_zeros() as usize + 1;
+        self.pending &= !sigmask(sig);
+        Some(sig)
+    }
+}
+
+pub fn is_valid(sig: usize) -> bool {
+    (1..=NSIG).contains(&sig)
+}
+
+/// What happens to a process that gets `sig` without a handler for it.
+/// Everything not listed here terminates. We can't stop processes, so
+/// the stop signals are ignored along with the ones that are harmless.
+fn ignored_by_default(sig: usize) -> bool {
+    matches!(
+        sig,
+        SIGCHLD | SIGCONT | SIGURG | SIGWINCH | SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU
+    )
+}
+
+/// Make `sig` pending for process `pid`. Only user processes can get
+/// signals. A process asleep or blocked in wait4 wakes up to take it,
+/// anything else sees it the next time it runs. Returns false if there
+/// is no such process.
+pub fn send_signal(pid: u16, sig: usize) -> bool {
+    with_table(|table| post_signal(table, pid, sig)).unwrap_or(false)
+}
+
+/// Send `sig` to every process in process group `pgid`, the way kill
+/// does with a pid of 0 or below. Returns false if nobody got it.
+pub fn send_group_signal(pgid: u16, sig: usize) -> bool {
+    with_table(|table| {
+        let pids: Vec<u16> = table.iter().filter(|p| p.pgid == pgid).map(|p| p.pid).collect();
+        let mut ret = false;
+        for pid in pids {
+            ret |= post_signal(table, pid, sig);
+        }
+        ret
+    })
+    .unwrap_or(false)
+}
+
+/// What [`send_signal`] does to a single process
+fn post_signal(table: &mut ProcessTable, pid: u16, sig: usize) -> bool {
+    let p = match table.get_mut(pid) {
+        Some(p) => p,
+        None => return false,
+    };
+    if unsafe { (*p.frame).mode } != CpuMode::User as usize {
+        return false;
+    }
+    // Signal 0 only checks that the process is there.
+    if is_valid(sig) && !matches!(p.state, ProcessState::Dead) {
+        p.signals.pending |= sigmask(sig);
+        // wait4 starts over once the handler returns, and a sleep is
+        // simply cut short.
+        let interruptible = match p.state {
+            ProcessState::Sleeping => true,
+            ProcessState::Waiting => p.interruptible,
+            _ => false,
+        };
+        if interruptible && sigmask(sig) & !p.signals.blocked != 0 && !p.signals.ignores(sig) {
+            p.interruptible = false;
+            table.set_state(pid, ProcessState::Running);
+        }
+    }
+    true
+}
+
+/// Send a signal that a process caused itself, such as SIGSEGV for a bad
+/// memory access. Running the same instruction again would only fault
+/// again, so unlike with [`send_signal`], blocking or ignoring it
+/// doesn't help: the process then dies of it. Returns true if that is
+/// what is going to happen, since there's no handler to catch it.
+pub unsafe fn force_signal(pid: u16, sig: usize) -> bool {
+    let p = get_by_pid(pid);
+    if p.is_null() {
+        return true;
+    }
+    let signals = &mut (*p).signals;
+    if signals.blocked & sigmask(sig) != 0 || signals.action(sig).handler == SIG_IGN {
+        signals.blocked &= !sigmask(sig);
+        signals.actions[sig - 1] = SigAction::default();
+    }
+    signals.pending |= sigmask(sig);
+    signals.action(sig).handler == SIG_DFL
+}
+
+/// Act on the next pending signal of whoever owns `frame`, which is about
+/// to run again. Ignored signals are dropped, and a handler is set up to
+/// run on the process' own stack. Returns false if the process was killed
+/// instead, in which case something else has to be scheduled. Kernel
+/// processes only ever get the signals they cause themselves, and since
+/// they have no handlers, those kill them.
+pub unsafe fn deliver(frame: *mut TrapFrame) -> bool {
+    if frame.is_null() {
+        return true;
+    }
+    let pid = (*frame).pid as u16;
+    let p = get_by_pid(pid);
+    if p.is_null() || !matches!((*p).state, ProcessState::Running) {
+        return true;
+    }
+    let signals = &mut (*p).signals;
+    let sig = match signals.take_next() {
+        Some(sig) => sig,
+        None => return true,
+    };
+    if signals.ignores(sig) {
+        return true;
+    }
+    let action = *signals.action(sig);
+    match action.handler {
+        SIG_DFL => {
+            with_table(|table| table.exit(pid, sig));
+            false
+        },
+        handler => {
+            let sigframe = SignalFrame {
+                regs: (*frame).regs,
+                fregs: (*frame).fregs,
+                pc: (*frame).pc,
+                blocked: signals.blocked,
+                sig,
+            };
+            let sp = (*frame).regs[Registers::Sp as usize]
+                .checked_sub(size_of::<SignalFrame>())
+                .map(|sp| sp & !15);
+            let sp = match sp {
+                Some(sp) if write_user(frame, sp, &sigframe).is_ok() => sp,
+                _ => {
+                    // There is nowhere to run the handler.
+                    with_table(|table| table.exit(pid, SIGSEGV));
+                    return false;
+                },
+            };
+            let mut blocked = signals.blocked | action.mask;
+            if action.flags & SA_NODEFER == 0 {
+                blocked |= sigmask(sig);
+            }
+            signals.set_blocked(blocked);
+            if action.flags & SA_RESETHAND != 0 {
+                signals.actions[sig - 1] = SigAction::default();
+            }
+            (*frame).regs[Registers::Sp as usize] = sp;
+            (*frame).regs[Registers::A0 as usize] = sig;
+            (*frame).regs[Registers::A1 as usize] = 0;
+            (*frame).regs[Registers::A2 as usize] = sp;
+            (*frame).regs[Registers::Ra as usize] = if action.flags & SA_RESTORER != 0 {
+                action.restorer
+            } else {
+                SIGRETURN_ADDR
+            };
+            (*frame).pc = handler;
+            true
+        },
+    }
+}
+
+/// Go back to where the process was before its handler ran, using the
+/// [`SignalFrame`] at its stack pointer. If the frame can't be read,
+/// the process gets a SIGSEGV instead.
+pub unsafe fn sigreturn(frame: *mut TrapFrame) {
+    let pid = (*frame).pid as u16;
+    let p = get_by_pid(pid);
+    if p.is_null() {
+        return;
+    }
+    match read_user::<SignalFrame>(frame, (*frame).regs[Registers::Sp as usize]) {
+        Ok(sigframe) => {
+            (*frame).regs = sigframe.regs;
+            (*frame).fregs = sigframe.fregs;
+            (*frame).pc = sigframe.pc;
+            (*p).signals.set_blocked(sigframe.blocked);
+        },
+        Err(_) => {
+            force_signal(pid, SIGSEGV);
+        },
+    }
+}
@@ -0,0 +1,182 @@
+//! # System call tracing
+//! Every system call of a traced process is logged to the console, the
+//! way strace does it: the name, the arguments, what it returned and how
+//! long that took. Processes switch tracing on with the trace system
+//! call, and `strace` on the kernel command line traces the first
+//! program from the start. Tracing survives execv, and with
+//! [`TRACE_FOLLOW`] it goes on to the children from fork as well.
+//!
+//! A system call that returns right away is logged as soon as its handler
+//! is done. One that puts its caller to sleep is logged once the caller
+//! runs again past the ecall, see [`finish`]. Until then, making the same
+//! call again after a wakeup still counts as the same call.
+use alloc::string::String;
+use core::fmt::Write;
+
+use crate::{
+    cpu::{
+        get_mtime,
+        Registers,
+        TrapFrame,
+        FREQ,
+    },
+    errno::to_ret,
+    process::get_by_pid,
+    syscall::{
+        Resume,
+        Syscall,
+    },
+    time::ticks_to,
+    uaccess::copy_string_from_user,
+};
+
+// Flags for the trace system call
+pub const TRACE_ON: usize = 1;
+pub const TRACE_FOLLOW: usize = 2;
+
+/// A system call that hasn't returned yet
+struct Call {
+    /// Name and arguments, written out when the call was made. A path
+    /// may be gone by the time the call returns.
+    text: String,
+    /// The ecall
+    pc: usize,
+    /// mtime when the call was made
+    start: usize,
+}
+
+/// How a traced process is traced
+pub struct Trace {
+    /// Whether the children from fork are traced, too
+    follow: bool,
+    call: Option<Call>,
+}
+
+impl Trace {
+    pub const fn new(follow: bool) -> Self {
+        Self { follow, call: None }
+    }
+
+    /// What a child gets on fork
+    pub const fn fork(&self) -> Option<Self> {
+        if self.follow { Some(Self::new(true)) } else { None }
+    }
+
+    /// Tracing as the trace system call sets it up, None for off
+    pub const fn from_flags(flags: usize) -> Option<Self> {
+        if flags & TRACE_ON == 0 {
+            None
+        } else {
+            Some(Self::new(flags & TRACE_FOLLOW != 0))
+        }
+    }
+}
+
+/// A system call argument, written the way it is most likely meant.
+/// Paths are read out of the caller's memory.
+unsafe fn write_arg(out: &mut String, frame: *const TrapFrame, name: &str, value: usize) {
+    if name == "path" {
+        if let Ok(path) = copy_string_from_user(frame, value) {
+            let _ = write!(out, "{:?}", path);
+            return;
+        }
+    }
+    // Small numbers and negative ones, such as AT_FDCWD, are counts,
+    // descriptors and the like. Anything else is likely an address.
+    if value < 0x1_0000 || (-0x1_0000..0).contains(&(value as isize)) {
+        let _ = write!(out, "{}", value as isize);
+    } else {
+        let _ = write!(out, "0x{:x}", value);
+    }
+}
+
+/// Log the end of `call` for `pid`. `ret` is what it returned, if that
+/// means anything.
+fn log(pid: u16, call: &Call, ret: Option<usize>) {
+    let ticks = get_mtime().wrapping_sub(call.start) as u64;
+    let secs = ticks / FREQ;
+    let micros = ticks_to(ticks % FREQ, 1_000_000);
+    match ret {
+        Some(ret) => println!(
+            "[pid {}] {} = {} <{}.{:06}>",
+            pid, call.text, ret as isize, secs, micros
+        ),
+        None => println!("[pid {}] {} = ? <{}.{:06}>", pid, call.text, secs, micros),
+    }
+}
+
+/// The process that owns `frame` is making `syscall`, if it's traced.
+pub unsafe fn enter(syscall: Syscall, frame: *const TrapFrame) {
+    let pid = (*frame).pid as u16;
+    let p = get_by_pid(pid);
+    let trace = match p.as_mut().and_then(|p| p.trace.as_mut()) {
+        Some(trace) => trace,
+        None => return,
+    };
+    let pc = (*frame).pc;
+    if let Some(call) = &trace.call {
+        if call.pc == pc {
+            // Woken up to make the call again
+            return;
+        }
+        // A signal handler came in between, and the call never returned.
+        log(pid, call, None);
+    }
+    let mut text = String::from(syscall.name());
+    text.push('(');
+    for (i, name) in syscall.args().iter().enumerate() {
+        if i > 0 {
+            text.push_str(", ");
+        }
+        let _ = write!(text, "{}=", name);
+        write_arg(&mut text, frame, name, (*frame).regs[Registers::A0 as usize + i]);
+    }
+    text.push(')');
+    let call = Call {
+        text,
+        pc,
+        start: get_mtime(),
+    };
+    if let Syscall::_Exit = syscall {
+        // There is nobody left to return to.
+        log(pid, &call, None);
+        return;
+    }
+    trace.call = Some(call);
+}
+
+/// The handler of the system call that `pid` is making is done with it.
+pub unsafe fn leave(pid: u16, resume: &Resume) {
+    let p = get_by_pid(pid);
+    let trace = match p.as_mut().and_then(|p| p.trace.as_mut()) {
+        Some(trace) => trace,
+        None => return,
+    };
+    let ret = match resume {
+        Resume::Return(result) => Some(to_ret(*result)),
+        Resume::At(_) => None,
+        // See finish
+        Resume::Schedule => return,
+    };
+    if let Some(call) = trace.call.take() {
+        log(pid, &call, ret);
+    }
+}
+
+/// The process that owns `frame` is about to run again. If it's past the
+/// ecall of a call that put it to sleep, the call has returned.
+pub unsafe fn finish(frame: *const TrapFrame) {
+    if frame.is_null() {
+        return;
+    }
+    let pid = (*frame).pid as u16;
+    let p = get_by_pid(pid);
+    let trace = match p.as_mut().and_then(|p| p.trace.as_mut()) {
+        Some(trace) => trace,
+        None => return,
+    };
+    if trace.call.as_ref().map_or(false, |call| call.pc != (*frame).pc) {
+        let call = trace.call.take().unwrap();
+        log(pid, &call, Some((*frame).regs[Registers::A0 as usize]));
+    }
+}
@@ -50,36 +50,120 @@
 
 use alloc::{
     boxed::Box,
+    collections::VecDeque,
+    rc::Rc,
     string::String,
+    vec,
+    vec::Vec,
+};
+use core::{
+    cell::RefCell,
+    convert::TryFrom,
+    fmt::Write,
+    mem::{
+        self,
+        size_of,
+    },
 };
-use core::convert::TryFrom;
 
 use crate::{
+    console::{
+        self,
+        Termios,
+    },
     cpu::{
         dump_registers,
+        satp_fence_asid,
+        CpuMode,
         Registers,
         TrapFrame,
     },
     elf,
-    fs,
+    errno::{
+        to_ret,
+        Errno,
+    },
+    fs::{
+        self,
+        Stat,
+    },
     page::{
-        map,
-        virt_to_phys,
-        EntryBits,
-        Table,
+        align_val,
         PAGE_SIZE,
     },
+    pipe::{
+        self,
+        PipeEnd,
+        PipeResult,
+        PIPE_SIZE,
+    },
+    power::{
+        self,
+        LINUX_REBOOT_CMD_CAD_OFF,
+        LINUX_REBOOT_CMD_CAD_ON,
+        LINUX_REBOOT_CMD_HALT,
+        LINUX_REBOOT_CMD_POWER_OFF,
+        LINUX_REBOOT_CMD_RESTART,
+        LINUX_REBOOT_CMD_RESTART2,
+    },
     process::{
         add_kernel_process_args,
-        delete_process,
         get_by_pid,
-        set_sleeping,
-        set_waiting,
-        PROCESS_LIST,
-        PROCESS_LIST_MUTEX,
+        with_table,
+        ChildStatus,
+        FileDescriptor,
+        OpenFile,
+        Process,
+        ProcessState,
+        ProcessTable,
+        OPEN_MAX,
+        PID_MAX,
+        WNOHANG,
+    },
+    sched::{
+        self,
+        NICE_MAX,
+        NICE_MIN,
+        PRIO_PGRP,
+        PRIO_PROCESS,
+    },
+    signal::{
+        is_valid,
+        send_group_signal,
+        send_signal,
+        sigreturn,
+        SigAction,
+        SIG_BLOCK,
+        SIG_SETMASK,
+        SIGPIPE,
+        SIG_UNBLOCK,
+    },
+    strace::{
+        self,
+        Trace,
+        TRACE_FOLLOW,
+        TRACE_ON,
+    },
+    time::{
+        self,
+        Timespec,
+        Timeval,
+    },
+    uaccess::{
+        access_ok,
+        copy_from_user,
+        copy_string_array_from_user,
+        copy_string_from_user,
+        copy_to_user,
+        read_user,
+        write_user,
+        AccessError,
     },
     virtio::{
-        block::block_op,
+        block::{
+            self,
+            block_op,
+        },
         gpu,
         input::{
             Event,
@@ -89,55 +173,209 @@ use crate::{
             KEY_OBSERVERS,
         },
     },
+    vma::{
+        Backing,
+        Vma,
+        MAP_ANONYMOUS,
+        MAP_FIXED,
+        MAP_PRIVATE,
+        MAP_SHARED,
+        MMAP_BASE,
+        MMAP_END,
+        PROT_EXEC,
+        PROT_READ,
+        PROT_WRITE,
+    },
     Buffer,
 };
 
-/// Contain all supported system calls
-#[repr(usize)]
-pub enum Syscall {
-    PutChar = 2,
-    DumpRegisters = 8,
-    Sleep = 10,
-    Execv = 11,
-    Read = 63,
-    _Exit = 93,
-    GetPid = 172,
-    BlockRead = 180,
-    GetFramebuffer = 1000,
-    TransferRectangleAndInvalidate = 1001,
-    WaitForKeyboardEvents = 1002,
-    WaitForAbsEvents = 1004,
-    GetTime = 1062,
-}
-
-/// Convert [`usize`] to [`Syscall`]
-///
-/// If value equal to [`Syscall`] descriminant
-/// then we return `Ok(Syscall::Variant)` else
-/// we return `Err(give_code)``
-impl TryFrom<usize> for Syscall {
-    type Error = usize;
-
-    fn try_from(syscall: usize) -> Result<Self, Self::Error> {
-        match syscall {
-            2 => Ok(Self::PutChar),
-            8 => Ok(Self::DumpRegisters),
-            10 => Ok(Self::Sleep),
-            11 => Ok(Self::Execv),
-            63 => Ok(Self::Read),
-            93 => Ok(Self::_Exit),
-            172 => Ok(Self::GetPid),
-            180 => Ok(Self::BlockRead),
-            1000 => Ok(Self::GetFramebuffer),
-            1001 => Ok(Self::TransferRectangleAndInvalidate),
-            1002 => Ok(Self::WaitForKeyboardEvents),
-            1004 => Ok(Self::WaitForAbsEvents),
-            1062 => Ok(Self::GetTime),
-            unexpected_syscal => Err(unexpected_syscal),
+/// The most we write in one system call. Longer writes come back short,
+/// just like they do with pipes.
+const MAX_WRITE: usize = 16 * PAGE_SIZE;
+/// The most buffers we take in a single writev
+const IOV_MAX: usize = 1024;
+
+/// How a handler leaves its caller when there's more to it than
+/// returning a value, see [`do_syscall`]
+pub enum Resume {
+    /// Put the result into A0 and schedule. The caller goes on after the
+    /// ecall once it gets to run again.
+    Return(Result<usize, Errno>),
+    /// Schedule, and leave A0 alone. Whoever wakes the caller up puts the
+    /// result there, or the caller makes the same system call again.
+    Schedule,
+    /// Go straight back to the caller at this program counter.
+    At(usize),
+}
+
+impl Resume {
+    /// Returns what [`do_syscall`] does.
+    unsafe fn finish(self, frame: *mut TrapFrame) -> usize {
+        match self {
+            Self::Return(result) => {
+                (*frame).regs[Registers::A0 as usize] = to_ret(result);
+                0
+            },
+            Self::Schedule => 0,
+            Self::At(pc) => pc,
         }
     }
 }
 
+/// What handlers can return. A plain result goes into A0.
+trait Outcome {
+    fn into_resume(self) -> Resume;
+}
+
+impl Outcome for Resume {
+    fn into_resume(self) -> Self {
+        self
+    }
+}
+
+impl Outcome for Result<usize, Errno> {
+    fn into_resume(self) -> Resume {
+        Resume::Return(self)
+    }
+}
+
+/// A type that handlers can take their arguments as. Registers are cut
+/// down to it like C would do it, so descriptors and pids come as usize
+/// and are checked by the handler, see [`fd_arg`] and [`pid_arg`].
+trait Arg {
+    fn from_reg(reg: usize) -> Self;
+}
+
+macro_rules! impl_arg {
+    ($($ty:ty),*) => {
+        $(impl Arg for $ty {
+            fn from_reg(reg: usize) -> Self {
+                reg as Self
+            }
+        })*
+    };
+}
+
+impl_arg!(usize, isize, u8, u32, u64);
+
+/// Declare the system call table. Every entry gives the number, the name
+/// userspace knows it by, and the handler with the arguments it takes
+/// from A0 onwards. Out of this come the [`Syscall`] enum, looking the
+/// numbers up, calling the handlers and the userspace header.
+macro_rules! syscalls {
+    ($($variant:ident = $number:literal, $name:literal => $handler:ident($($arg:ident: $ty:ty),*);)*) => {
+        /// Contain all supported system calls
+        #[repr(usize)]
+        #[derive(Copy, Clone)]
+        pub enum Syscall {
+            $($variant = $number,)*
+        }
+
+        /// Convert [`usize`] to [`Syscall`]
+        ///
+        /// If value equal to [`Syscall`] descriminant
+        /// then we return `Ok(Syscall::Variant)` else
+        /// we return `Err(give_code)``
+        impl TryFrom<usize> for Syscall {
+            type Error = usize;
+
+            fn try_from(syscall: usize) -> Result<Self, Self::Error> {
+                match syscall {
+                    $($number => Ok(Self::$variant),)*
+                    unexpected_syscall => Err(unexpected_syscall),
+                }
+            }
+        }
+
+        impl Syscall {
+            /// The whole table, in order
+            pub const ALL: &'static [Self] = &[$(Self::$variant),*];
+
+            /// The name userspace knows this by
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Self::$variant => $name,)*
+                }
+            }
+
+            /// The names of the arguments, as many as there are
+            pub fn args(self) -> &'static [&'static str] {
+                match self {
+                    $(Self::$variant => &[$(stringify!($arg)),*],)*
+                }
+            }
+
+            /// Take the arguments out of the caller's registers and run
+            /// the handler.
+            unsafe fn call(self, frame: *mut TrapFrame) -> Resume {
+                let mut regs = (Registers::A0 as usize..=Registers::A5 as usize).map(|i| (*frame).regs[i]);
+                match self {
+                    $(Self::$variant => {
+                        $handler(frame, $(<$ty as Arg>::from_reg(regs.next().unwrap())),*).into_resume()
+                    },)*
+                }
+            }
+        }
+    };
+}
+
+syscalls! {
+    PutChar = 2, "put_char" => sys_put_char(c: u8);
+    DumpRegisters = 8, "dump_registers" => sys_dump_registers();
+    Yield = 9, "yield" => sys_yield();
+    Sleep = 10, "sleep" => sys_sleep(duration: usize);
+    Execv = 11, "execv" => sys_execv(path: usize, argv: usize, envp: usize);
+    Getcwd = 17, "getcwd" => sys_getcwd(buf: usize, size: usize);
+    Dup = 23, "dup" => sys_dup(oldfd: usize);
+    Dup3 = 24, "dup3" => sys_dup3(oldfd: usize, newfd: usize, flags: usize);
+    Fcntl = 25, "fcntl" => sys_fcntl(fd: usize, cmd: usize, arg: usize);
+    Ioctl = 29, "ioctl" => sys_ioctl(fd: usize, request: usize, argp: usize);
+    Chdir = 49, "chdir" => sys_chdir(path: usize);
+    OpenAt = 56, "openat" => sys_openat(dirfd: usize, path: usize, flags: usize, mode: usize);
+    Close = 57, "close" => sys_close(fd: usize);
+    Pipe2 = 59, "pipe2" => sys_pipe2(fds: usize, flags: usize);
+    Getdents64 = 61, "getdents64" => sys_getdents64(fd: usize, dirp: usize, count: usize);
+    Lseek = 62, "lseek" => sys_lseek(fd: usize, offset: isize, whence: usize);
+    Read = 63, "read" => sys_read(fd: usize, buf: usize, count: usize);
+    Write = 64, "write" => sys_write(fd: usize, buf: usize, count: usize);
+    Writev = 66, "writev" => sys_writev(fd: usize, iov: usize, iovcnt: usize);
+    Fstatat = 79, "fstatat" => sys_fstatat(dirfd: usize, path: usize, statbuf: usize, flags: usize);
+    Fstat = 80, "fstat" => sys_fstat(fd: usize, statbuf: usize);
+    _Exit = 93, "exit" => sys_exit(status: usize);
+    ClockGettime = 113, "clock_gettime" => sys_clock_gettime(clockid: usize, tp: usize);
+    Kill = 129, "kill" => sys_kill(pid: isize, sig: usize);
+    RtSigaction = 134, "rt_sigaction" => sys_rt_sigaction(sig: usize, act: usize, oldact: usize, sigsetsize: usize);
+    RtSigprocmask = 135, "rt_sigprocmask" => sys_rt_sigprocmask(how: usize, set: usize, oldset: usize, sigsetsize: usize);
+    RtSigreturn = 139, "rt_sigreturn" => sys_rt_sigreturn();
+    SetPriority = 140, "setpriority" => sys_setpriority(which: usize, who: usize, prio: isize);
+    GetPriority = 141, "getpriority" => sys_getpriority(which: usize, who: usize);
+    Reboot = 142, "reboot" => sys_reboot(magic1: u32, magic2: u32, cmd: u32, arg: usize);
+    Times = 153, "times" => sys_times(buf: usize);
+    SetPgid = 154, "setpgid" => sys_setpgid(pid: usize, pgid: usize);
+    GetPgid = 155, "getpgid" => sys_getpgid(pid: usize);
+    Uname = 160, "uname" => sys_uname(buf: usize);
+    Gettimeofday = 169, "gettimeofday" => sys_gettimeofday(tv: usize, tz: usize);
+    GetPid = 172, "getpid" => sys_getpid();
+    BlockRead = 180, "block_read" => sys_block_read(dev: usize, buf: usize, size: usize, offset: u64);
+    Brk = 214, "brk" => sys_brk(addr: usize);
+    Munmap = 215, "munmap" => sys_munmap(addr: usize, length: usize);
+    Fork = 220, "fork" => sys_fork();
+    Mmap = 222, "mmap" => sys_mmap(addr: usize, length: usize, prot: usize, flags: usize, fd: usize, offset: usize);
+    Mprotect = 226, "mprotect" => sys_mprotect(addr: usize, length: usize, prot: usize);
+    Wait4 = 260, "wait4" => sys_wait4(pid: isize, status: usize, options: usize, rusage: usize);
+    // System calls 1000 and above are "special" system calls for our OS. I'll
+    // try to mimic the normal system calls below 1000 so that this OS is compatible
+    // with libraries.
+    GetFramebuffer = 1000, "get_fb" => sys_get_fb(dev: usize);
+    TransferRectangleAndInvalidate = 1001, "inv_rect" => sys_inv_rect(dev: usize, x: u32, y: u32, width: u32, height: u32);
+    WaitForKeyboardEvents = 1002, "get_key" => sys_get_key(events: usize, max: usize);
+    WaitForAbsEvents = 1004, "get_abs" => sys_get_abs(events: usize, max: usize);
+    Trace = 1005, "trace" => sys_trace(pid: usize, flags: usize);
+    Stat = 1038, "stat" => sys_stat(path: usize, statbuf: usize);
+    Dup2 = 1041, "dup2" => sys_dup2(oldfd: usize, newfd: usize);
+    GetTime = 1062, "get_time" => sys_get_time();
+}
+
 /// Return [`Syscall`] variant descriminant
 impl From<Syscall> for usize {
     fn from(syscall: Syscall) -> Self {
@@ -148,6 +386,49 @@ impl From<Syscall> for usize {
     }
 }
 
+/// What the userspace header starts with
+const HEADER_START: &str = r#"// Generated from the system call table in src/syscall.rs by `just syscall_header`.
+#pragma once
+
+extern "C" {
+    unsigned long make_syscall(
+        unsigned long sysno,
+        unsigned long a1=0,
+        unsigned long a2=0,
+        unsigned long a3=0,
+        unsigned long a4=0,
+        unsigned long a5=0,
+        unsigned long a6=0
+    );
+}
+
+"#;
+
+/// The userspace `syscall.h`: a `SYS_` number and a `syscall_` macro for
+/// everything in the table. `just syscall_header` writes it out.
+pub fn header() -> String {
+    let mut out = String::from(HEADER_START);
+    for &syscall in Syscall::ALL {
+        let _ = writeln!(out, "#define SYS_{} {}", syscall.name(), syscall as usize);
+    }
+    out.push('\n');
+    for &syscall in Syscall::ALL {
+        let args = syscall.args();
+        let _ = write!(
+            out,
+            "#define syscall_{}({}) make_syscall(SYS_{}",
+            syscall.name(),
+            args.join(", "),
+            syscall.name()
+        );
+        for arg in args {
+            let _ = write!(out, ", (unsigned long)({})", arg);
+        }
+        out.push_str(")\n");
+    }
+    out
+}
+
 /// System calls handler
 ///
 /// [`do_syscall`], is called from trap.rs to invoke a system call. No discernment is
@@ -156,228 +437,1176 @@ impl From<Syscall> for usize {
 /// I went ahead and made the entire function unsafe.
 /// If we return 0 from this function, the `m_trap` function will schedule
 /// the next process--consider this a yield. A non-0 is the program counter
-/// we want to go back to.
+/// we want to go back to. Until then, the program counter in the trap
+/// frame is `mepc`, on the ecall.
 pub unsafe fn do_syscall(mepc: usize, frame: *mut TrapFrame) -> usize {
     // Libgloss expects the system call number in A7, so let's follow
     // their lead.
     // A7 is X17, so it's register number 17.
-    Syscall::try_from((*frame).regs[Registers::A7 as usize]).map_or_else(
-        |unexpected_syscall| {
-            println!("Unknown syscall number {}", unexpected_syscall);
+    match Syscall::try_from((*frame).regs[Registers::A7 as usize]) {
+        Ok(syscall) => {
+            let pid = (*frame).pid as u16;
+            strace::enter(syscall, frame);
+            let resume = syscall.call(frame);
+            strace::leave(pid, &resume);
+            resume.finish(frame)
+        },
+        Err(_) => {
+            (*frame).regs[Registers::A0 as usize] = Errno::ENOSYS.to_ret();
+            mepc + 4
+        },
+    }
+}
+
+unsafe fn sys_put_char(_frame: *mut TrapFrame, c: u8) -> Resume {
+    print!("{}", c as char);
+    Resume::Schedule
+}
+
+unsafe fn sys_dump_registers(frame: *mut TrapFrame) -> Resume {
+    dump_registers(frame);
+    Resume::At((*frame).pc + 4)
+}
+
+unsafe fn sys_yield(_frame: *mut TrapFrame) -> Resume {
+    Resume::Schedule
+}
+
+unsafe fn sys_sleep(frame: *mut TrapFrame, duration: usize) -> Resume {
+    with_table(|table| table.sleep((*frame).pid as u16, duration));
+    Resume::Schedule
+}
+
+/// `argv` can be NULL for just the path, and `envp` NULL to keep our
+/// environment.
+unsafe fn sys_execv(frame: *mut TrapFrame, path: usize, argv: usize, envp: usize) -> Resume {
+    let path = match copy_string_from_user(frame, path) {
+        Ok(path) => path,
+        Err(error) => return Resume::Return(Err(error.into())),
+    };
+    // Everything has to be copied out now, the caller's memory is
+    // gone by the time the new program is set up.
+    let argv = match argv {
+        0 => Ok(vec![path.clone()]),
+        v_addr => copy_string_array_from_user(frame, v_addr),
+    };
+    let p = match current(frame) {
+        Ok(p) => p,
+        Err(error) => return Resume::Return(Err(error)),
+    };
+    let envp = match envp {
+        0 => Ok((*p).data.environ_strings()),
+        v_addr => copy_string_array_from_user(frame, v_addr),
+    };
+    let (argv, envp) = match (argv, envp) {
+        (Ok(argv), Ok(envp)) => (argv, envp),
+        (Err(error), _) | (_, Err(error)) => return Resume::Return(Err(error.into())),
+    };
+    // See if we can find the path.
+    match fs::MinixFileSystem::open(8, &fs::resolve_path((*p).data.cwd(), &path)) {
+        Ok(inode) => {
+            let args = Box::new(ExecArgs {
+                pid: (*frame).pid as u16,
+                inode,
+                argv,
+                envp,
+            });
+            // The Box above moves the arguments to a new memory location on the heap.
+            // This needs to be on the heap since we are about to hand over control
+            // to a kernel process.
+            // We have to make sure we relinquish Box control here by using into_raw.
+            // Otherwise, the Box will free the memory associated with this inode.
+            add_kernel_process_args(exec_func, Box::into_raw(args) as usize);
+            // We wait until the kernel process has replaced our program with the
+            // new one. If that fails, it wakes us up with an error instead, so
+            // our parent can still see us exit.
+            with_table(|table| table.set_state((*frame).pid as u16, ProcessState::Waiting));
+            Resume::Schedule
+        },
+        // If we get here, the path couldn't be found, or for some reason
+        // open failed. So, we return the error and move on.
+        Err(error) => Resume::Return(Err(error.into())),
+    }
+}
+
+/// Like Linux, this returns the length of the path, including the
+/// terminating NUL.
+unsafe fn sys_getcwd(frame: *mut TrapFrame, buf: usize, size: usize) -> Result<usize, Errno> {
+    let p = current(frame)?;
+    let mut cwd = String::from((*p).data.cwd());
+    cwd.push('\0');
+    if cwd.len() > size {
+        return Err(Errno::ERANGE);
+    }
+    copy_to_user(frame, buf, cwd.as_ptr(), cwd.len())?;
+    Ok(cwd.len())
+}
+
+unsafe fn sys_chdir(frame: *mut TrapFrame, path: usize) -> Result<usize, Errno> {
+    let path = user_path(frame, fs::AT_FDCWD as usize, path)?;
+    let inode = fs::MinixFileSystem::open(8, &path)?;
+    if inode.mode & fs::S_IFDIR == 0 {
+        return Err(Errno::ENOTDIR);
+    }
+    (*current(frame)?).data.set_cwd(path);
+    Ok(0)
+}
+
+unsafe fn sys_openat(
+    frame: *mut TrapFrame,
+    dirfd: usize,
+    path: usize,
+    flags: usize,
+    _mode: usize,
+) -> Result<usize, Errno> {
+    let path = user_path(frame, dirfd, path)?;
+    let (ino, inode) = fs::MinixFileSystem::lookup(8, &path)?;
+    // The Minix driver can't write yet, so only read-only opens
+    // make sense.
+    if flags & fs::O_ACCMODE != fs::O_RDONLY {
+        return Err(Errno::EROFS);
+    }
+    let desc = FileDescriptor::File(Rc::new(RefCell::new(OpenFile {
+        bdev: 8,
+        ino,
+        inode,
+        offset: 0,
+        flags,
+        path,
+    })));
+    Ok((*current(frame)?).data.add_fd(desc).ok_or(Errno::EMFILE)? as usize)
+}
+
+unsafe fn sys_close(frame: *mut TrapFrame, fd: usize) -> Result<usize, Errno> {
+    let fd = fd_arg(fd)?;
+    let p = current(frame)?;
+    match (*p).data.remove_fd(fd) {
+        Some(_) => Ok(0),
+        None => Err(Errno::EBADF),
+    }
+}
+
+unsafe fn sys_lseek(frame: *mut TrapFrame, fd: usize, offset: isize, whence: usize) -> Result<usize, Errno> {
+    let fd = fd_arg(fd)?;
+    let p = current(frame)?;
+    let mut f = match (*p).data.fdesc.get(&fd) {
+        Some(FileDescriptor::File(f)) => f.borrow_mut(),
+        Some(_) => return Err(Errno::ESPIPE),
+        None => return Err(Errno::EBADF),
+    };
+    let base = match whence {
+        fs::SEEK_SET => 0,
+        fs::SEEK_CUR => f.offset as isize,
+        fs::SEEK_END => f.inode.size as isize,
+        _ => return Err(Errno::EINVAL),
+    };
+    let new_offset = match base.checked_add(offset) {
+        Some(new_offset) if new_offset >= 0 => new_offset,
+        _ => return Err(Errno::EINVAL),
+    };
+    f.offset = new_offset as usize;
+    Ok(f.offset)
+}
+
+/// Reading a file is an asynchronous call. This will get the process
+/// going. We won't hear the answer until we an interrupt back.
+unsafe fn sys_read(frame: *mut TrapFrame, fd: usize, buf: usize, count: usize) -> Resume {
+    let fd = match fd_arg(fd) {
+        Ok(fd) => fd,
+        Err(error) => return Resume::Return(Err(error)),
+    };
+    let p = match current(frame) {
+        Ok(p) => p,
+        Err(error) => return Resume::Return(Err(error)),
+    };
+    let (bdev, inode, offset) = match (*p).data.fdesc.get(&fd) {
+        Some(FileDescriptor::File(f)) if f.borrow().flags & fs::O_ACCMODE != fs::O_WRONLY => {
+            let f = f.borrow();
+            (f.bdev, f.inode, f.offset)
+        },
+        Some(FileDescriptor::Pipe(end)) if end.flags & fs::O_ACCMODE != fs::O_WRONLY => {
+            return syscall_pipe_read(frame, end, buf, count);
+        },
+        Some(FileDescriptor::Device(console::CONSOLE)) => {
+            return syscall_console_read(frame, buf, count);
+        },
+        _ => return Resume::Return(Err(Errno::EBADF)),
+    };
+    // MinixFileSystem::read only clamps the size against the
+    // size of the file, not against what is left after the
+    // offset, so we do that here.
+    let left = (inode.size as usize).saturating_sub(offset);
+    let size = count.min(left);
+    if size == 0 {
+        return Resume::Return(Ok(0));
+    }
+    // Make sure the whole buffer is there before we go to the
+    // disk. The kernel process copies the data out page by
+    // page once the read is done.
+    if let Err(error) = access_ok(frame, buf, size, true) {
+        return Resume::Return(Err(error.into()));
+    }
+    fs::process_read((*frame).pid as u16, fd, bdev, inode, buf, size as u32, offset as u32);
+    Resume::Schedule
+}
+
+unsafe fn sys_write(frame: *mut TrapFrame, fd: usize, buf: usize, count: usize) -> Resume {
+    match fd_arg(fd) {
+        Ok(fd) => syscall_write(frame, fd, &[(buf, count)]),
+        Err(error) => Resume::Return(Err(error)),
+    }
+}
+
+/// Each iovec is a base address followed by a length.
+unsafe fn sys_writev(frame: *mut TrapFrame, fd: usize, iov: usize, iovcnt: usize) -> Resume {
+    let fd = match fd_arg(fd) {
+        Ok(fd) => fd,
+        Err(error) => return Resume::Return(Err(error)),
+    };
+    if iovcnt > IOV_MAX {
+        return Resume::Return(Err(Errno::EINVAL));
+    }
+    let mut entries = Vec::new();
+    for i in 0..iovcnt {
+        // An array that runs off the end of the address space isn't there.
+        let entry = match i
+            .checked_mul(size_of::<(usize, usize)>())
+            .and_then(|off| iov.checked_add(off))
+        {
+            Some(entry) => entry,
+            None => return Resume::Return(Err(Errno::EFAULT)),
+        };
+        match read_user::<(usize, usize)>(frame, entry) {
+            Ok(entry) => entries.push(entry),
+            Err(error) => return Resume::Return(Err(error.into())),
+        }
+    }
+    syscall_write(frame, fd, &entries)
+}
+
+unsafe fn sys_getdents64(frame: *mut TrapFrame, fd: usize, dirp: usize, count: usize) -> Resume {
+    let fd = match fd_arg(fd) {
+        Ok(fd) => fd,
+        Err(error) => return Resume::Return(Err(error)),
+    };
+    let p = match current(frame) {
+        Ok(p) => p,
+        Err(error) => return Resume::Return(Err(error)),
+    };
+    let size = count.min(PAGE_SIZE * 16);
+    match (*p).data.fdesc.get(&fd) {
+        Some(FileDescriptor::File(f)) if f.borrow().inode.mode & fs::S_IFDIR != 0 => {
+            let f = f.borrow();
+            if let Err(error) = access_ok(frame, dirp, size, true) {
+                return Resume::Return(Err(error.into()));
+            }
+            // The kernel process wakes us up with the result.
+            fs::process_getdents((*frame).pid as u16, fd, f.bdev, f.inode, dirp, size, f.offset);
+            Resume::Schedule
+        },
+        Some(_) => Resume::Return(Err(Errno::ENOTDIR)),
+        None => Resume::Return(Err(Errno::EBADF)),
+    }
+}
+
+/// With AT_EMPTY_PATH and an empty path, this is fstat on `dirfd`.
+unsafe fn sys_fstatat(
+    frame: *mut TrapFrame,
+    dirfd: usize,
+    path: usize,
+    statbuf: usize,
+    flags: usize,
+) -> Result<usize, Errno> {
+    let stat = if flags & fs::AT_EMPTY_PATH != 0 && matches!(read_user::<u8>(frame, path), Ok(0)) {
+        fd_stat(frame, fd_arg(dirfd)?)?
+    } else {
+        path_stat(&user_path(frame, dirfd, path)?)?
+    };
+    write_user(frame, statbuf, &stat)?;
+    Ok(0)
+}
+
+unsafe fn sys_fstat(frame: *mut TrapFrame, fd: usize, statbuf: usize) -> Result<usize, Errno> {
+    let fd = fd_arg(fd)?;
+    let stat = fd_stat(frame, fd)?;
+    write_user(frame, statbuf, &stat)?;
+    Ok(0)
+}
+
+/// This is the stat newlib makes on its own.
+unsafe fn sys_stat(frame: *mut TrapFrame, path: usize, statbuf: usize) -> Result<usize, Errno> {
+    let stat = path_stat(&user_path(frame, fs::AT_FDCWD as usize, path)?)?;
+    write_user(frame, statbuf, &stat)?;
+    Ok(0)
+}
+
+/// `fds` is an int[2].
+unsafe fn sys_pipe2(frame: *mut TrapFrame, fds: usize, flags: usize) -> Result<usize, Errno> {
+    let p = current(frame)?;
+    let (read, write) = pipe::pipe(flags);
+    let read = (*p).data.add_fd(FileDescriptor::Pipe(read)).ok_or(Errno::EMFILE)?;
+    let write = match (*p).data.add_fd(FileDescriptor::Pipe(write)) {
+        Some(write) => write,
+        None => {
+            (*p).data.remove_fd(read);
+            return Err(Errno::EMFILE);
+        },
+    };
+    if let Err(error) = write_user(frame, fds, &[read as i32, write as i32]) {
+        (*p).data.remove_fd(read);
+        (*p).data.remove_fd(write);
+        return Err(error.into());
+    }
+    (*p).data.set_cloexec(read, flags & fs::O_CLOEXEC != 0);
+    (*p).data.set_cloexec(write, flags & fs::O_CLOEXEC != 0);
+    Ok(0)
+}
+
+unsafe fn sys_dup(frame: *mut TrapFrame, oldfd: usize) -> Result<usize, Errno> {
+    let oldfd = fd_arg(oldfd)?;
+    let p = current(frame)?;
+    let desc = (*p).data.fdesc.get(&oldfd).ok_or(Errno::EBADF)?.clone();
+    Ok((*p).data.add_fd(desc).ok_or(Errno::EMFILE)? as usize)
+}
+
+/// Unlike dup2, duplicating a descriptor onto itself is an error.
+unsafe fn sys_dup3(frame: *mut TrapFrame, oldfd: usize, newfd: usize, flags: usize) -> Result<usize, Errno> {
+    if oldfd == newfd || flags & !fs::O_CLOEXEC != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let (oldfd, newfd) = (fd_arg(oldfd)?, fd_arg(newfd)?);
+    let p = current(frame)?;
+    let desc = (*p).data.fdesc.get(&oldfd).ok_or(Errno::EBADF)?.clone();
+    (*p).data.set_fd(newfd, desc);
+    (*p).data.set_cloexec(newfd, flags & fs::O_CLOEXEC != 0);
+    Ok(newfd as usize)
+}
+
+/// Linux has no dup2 on RISC-V, so this one has the number it had before
+/// dup3 came along. Duplicating a descriptor onto itself does nothing.
+unsafe fn sys_dup2(frame: *mut TrapFrame, oldfd: usize, newfd: usize) -> Result<usize, Errno> {
+    if oldfd != newfd {
+        return sys_dup3(frame, oldfd, newfd, 0);
+    }
+    let p = current(frame)?;
+    (*p).data.fdesc.get(&fd_arg(oldfd)?).ok_or(Errno::EBADF)?;
+    Ok(newfd)
+}
+
+unsafe fn sys_fcntl(frame: *mut TrapFrame, fd: usize, cmd: usize, arg: usize) -> Result<usize, Errno> {
+    let fd = fd_arg(fd)?;
+    let p = current(frame)?;
+    let desc = (*p).data.fdesc.get_mut(&fd).ok_or(Errno::EBADF)?;
+    Ok(match cmd {
+        fs::F_DUPFD | fs::F_DUPFD_CLOEXEC => {
+            if arg >= OPEN_MAX {
+                return Err(Errno::EINVAL);
+            }
+            let desc = desc.clone();
+            let newfd = (*p).data.add_fd_from(desc, arg as u16).ok_or(Errno::EMFILE)?;
+            (*p).data.set_cloexec(newfd, cmd == fs::F_DUPFD_CLOEXEC);
+            newfd as usize
+        },
+        fs::F_GETFD => {
+            if (*p).data.is_cloexec(fd) {
+                fs::FD_CLOEXEC
+            } else {
+                0
+            }
+        },
+        fs::F_SETFD => {
+            (*p).data.set_cloexec(fd, arg & fs::FD_CLOEXEC != 0);
             0
         },
-        |syscall| {
-            match syscall {
-                Syscall::_Exit => {
-                    delete_process((*frame).pid as u16);
-                    0
-                },
-                Syscall::PutChar => {
-                    print!("{}", (*frame).regs[Registers::A0 as usize] as u8 as char);
-                    0
-                },
-                Syscall::DumpRegisters => {
-                    dump_registers(frame);
-                    mepc + 4
-                },
-                Syscall::Sleep => {
-                    set_sleeping((*frame).pid as u16, (*frame).regs[Registers::A0 as usize]);
-                    0
-                },
-                Syscall::Execv => {
-                    // A0 = path
-                    // A1 = argv
-                    let mut path_addr = (*frame).regs[Registers::A0 as usize];
-                    // If the MMU is turned on, translate.
-                    if (*frame).satp >> 60 != 0 {
-                        let p = get_by_pid((*frame).pid as u16);
-                        let table = ((*p).get_table_address() as *mut Table).as_ref().unwrap();
-                        path_addr = virt_to_phys(table, path_addr).unwrap();
-                    }
-                    // Our path address here is now a physical address. If it came in virtual,
-                    // it is now physical.
-                    let path_bytes = path_addr as *const u8;
-                    let mut path = String::new();
-                    let mut iterator: usize = 0;
-                    // I really have to figure out how to change an array of bytes
-                    // to a string. For now, this is very C-style and mimics strcpy.
-                    loop {
-                        let ch = *path_bytes.add(iterator);
-                        if ch == 0 {
-                            break;
-                        }
-                        iterator += 1;
-                        path.push(ch as char);
-                    }
-                    // See if we can find the path.
-                    if let Ok(inode) = fs::MinixFileSystem::open(8, &path) {
-                        let inode_heap = Box::new(inode);
-                        // The Box above moves the Inode to a new memory location on the heap.
-                        // This needs to be on the heap since we are about to hand over control
-                        // to a kernel process.
-                        // THERE is an issue here. If we fail somewhere inside the kernel process,
-                        // we shouldn't delete our process here. However, since this is asynchronous
-                        // our process will still get deleted and the error won't be reported.
-                        // We have to make sure we relinquish Box control here by using into_raw.
-                        // Otherwise, the Box will free the memory associated with this inode.
-                        add_kernel_process_args(exec_func, Box::into_raw(inode_heap) as usize);
-                        // This deletes us, which is what we want.
-                        delete_process((*frame).pid as u16);
-                        0
-                    } else {
-                        // If we get here, the path couldn't be found, or for some reason
-                        // open failed. So, we return -1 and move on.
-                        println!("Could not open path '{}'.", path);
-                        (*frame).regs[Registers::A0 as usize] = usize::MAX;
-                        mepc + 4
-                    }
-                },
-                Syscall::Read => {
-                    // This is an asynchronous call. This will get the
-                    // process going. We won't hear the answer until
-                    // we an interrupt back.
-                    // TODO: The buffer is a virtual memory address that
-                    // needs to be translated to a physical memory location.
-                    // This needs to be put into a process and ran.
-                    // The buffer (regs[12]) needs to be translated when ran
-                    // from a user process using virt_to_phys. If this turns
-                    // out to be a page fault, we need to NOT proceed with
-                    // the read!
-                    // If the MMU is turned on, we have to translate the
-                    // address. Eventually, I will put this code into a
-                    // convenient function, but for now, it will show how
-                    // translation will be done.
-                    let physical_buffer = if (*frame).satp != 0 {
-                        let p = get_by_pid((*frame).pid as u16);
-                        let table = ((*p).get_table_address() as *mut Table).as_ref().unwrap();
-                        let paddr = virt_to_phys(table, (*frame).regs[12]);
-                        if paddr.is_none() {
-                            (*frame).regs[Registers::A0 as usize] = usize::MAX;
-                            return 0;
-                        }
-                        paddr.unwrap()
-                    } else {
-                        (*frame).regs[Registers::A2 as usize]
-                    };
-                    // TODO: Not only do we need to check the buffer, but it
-                    // is possible that the buffer spans multiple pages. We
-                    // need to check all pages that this might span. We
-                    // can't just do paddr and paddr + size, since there
-                    // could be a missing page somewhere in between.
-                    fs::process_read(
-                        (*frame).pid as u16,
-                        (*frame).regs[Registers::A0 as usize] as usize,
-                        (*frame).regs[Registers::A1 as usize] as u32,
-                        physical_buffer as *mut u8,
-                        (*frame).regs[Registers::A3 as usize] as u32,
-                        (*frame).regs[Registers::A4 as usize] as u32,
-                    );
-                    // If we return 0, the trap handler will schedule
-                    // another process.
-                    0
-                },
-                Syscall::GetPid => {
-                    // A0 = pid
-                    (*frame).regs[Registers::A0 as usize] = (*frame).pid;
-                    0
-                },
-                Syscall::BlockRead => {
-                    set_waiting((*frame).pid as u16);
-                    let _ = block_op(
-                        (*frame).regs[Registers::A0 as usize],
-                        (*frame).regs[Registers::A1 as usize] as *mut u8,
-                        (*frame).regs[Registers::A2 as usize] as u32,
-                        (*frame).regs[Registers::A3 as usize] as u64,
-                        false,
-                        (*frame).pid as u16,
-                    );
-                    0
-                },
-                // System calls 1000 and above are "special" system calls for our OS. I'll
-                // try to mimic the normal system calls below 1000 so that this OS is compatible
-                // with libraries.
-                Syscall::GetFramebuffer => {
-                    // syscall_get_framebuffer(device)
-                    let dev = (*frame).regs[Registers::A0 as usize];
-                    (*frame).regs[Registers::A0 as usize] = 0;
-                    if dev > 0 && dev <= 8 {
-                        if let Some(p) = gpu::GPU_DEVICES[dev - 1].take() {
-                            let ptr = p.get_framebuffer() as usize;
-                            if (*frame).satp >> 60 != 0 {
-                                let process = get_by_pid((*frame).pid as u16);
-                                let table = ((*process).get_table_address() as *mut Table).as_mut().unwrap();
-                                let num_pages = (p.get_width() * p.get_height() * 4) as usize / PAGE_SIZE;
-                                for i in 0..num_pages {
-                                    let vaddr = 0x3000_0000 + (i << 12);
-                                    let paddr = ptr + (i << 12);
-                                    map(table, vaddr, paddr, EntryBits::UserReadWrite as i64, 0);
-                                }
-                                gpu::GPU_DEVICES[dev - 1].replace(p);
-                            }
-                            (*frame).regs[Registers::A0 as usize] = 0x3000_0000;
-                        }
-                    }
-                    0
-                },
-                Syscall::TransferRectangleAndInvalidate => {
-                    let dev = (*frame).regs[Registers::A0 as usize];
-                    let x = (*frame).regs[Registers::A1 as usize] as u32;
-                    let y = (*frame).regs[Registers::A2 as usize] as u32;
-                    let width = (*frame).regs[Registers::A3 as usize] as u32;
-                    let height = (*frame).regs[Registers::A4 as usize] as u32;
-                    gpu::transfer(dev, x, y, width, height);
-                    0
-                },
-                Syscall::WaitForKeyboardEvents => {
-                    let mut ev = KEY_EVENTS.take().unwrap();
-                    let max_events = (*frame).regs[Registers::A1 as usize];
-                    let vaddr = (*frame).regs[Registers::A0 as usize] as *const Event;
-                    if (*frame).satp >> 60 != 0 {
-                        let process = get_by_pid((*frame).pid as u16);
-                        let table = ((*process).get_table_address() as *mut Table).as_mut().unwrap();
-                        (*frame).regs[Registers::A0 as usize] = 0;
-                        for i in 0..if max_events <= ev.len() { max_events } else { ev.len() } {
-                            let paddr = virt_to_phys(table, vaddr.add(i) as usize);
-                            if paddr.is_none() {
-                                break;
-                            }
-                            let paddr = paddr.unwrap() as *mut Event;
-                            *paddr = ev.pop_front().unwrap();
-                            (*frame).regs[Registers::A0 as usize] += 1;
-                        }
-                    }
-                    KEY_EVENTS.replace(ev);
-                    0
-                },
-                Syscall::WaitForAbsEvents => {
-                    let mut ev = ABS_EVENTS.take().unwrap();
-                    let max_events = (*frame).regs[Registers::A1 as usize];
-                    let v_addr = (*frame).regs[Registers::A0 as usize] as *const Event;
-                    if (*frame).satp >> 60 != 0 {
-                        let process = get_by_pid((*frame).pid as u16);
-                        let table = ((*process).get_table_address() as *mut Table).as_mut().unwrap();
-                        (*frame).regs[Registers::A0 as usize] = 0;
-                        for i in 0..if max_events <= ev.len() { max_events } else { ev.len() } {
-                            let paddr = virt_to_phys(table, v_addr.add(i) as usize);
-                            if paddr.is_none() {
-                                break;
-                            }
-                            let p_addr = paddr.unwrap() as *mut Event;
-                            *p_addr = ev.pop_front().unwrap();
-                            (*frame).regs[Registers::A0 as usize] += 1;
-                        }
-                    }
-                    ABS_EVENTS.replace(ev);
-                    0
+        fs::F_GETFL => match desc {
+            FileDescriptor::File(f) => f.borrow().flags,
+            FileDescriptor::Pipe(end) => end.flags,
+            _ => fs::O_RDWR,
+        },
+        // Of the status flags, only O_NONBLOCK can be
+        // changed, and it only makes a difference to pipes.
+        fs::F_SETFL => {
+            match desc {
+                FileDescriptor::File(f) => {
+                    let mut f = f.borrow_mut();
+                    f.flags = f.flags & !fs::O_NONBLOCK | arg & fs::O_NONBLOCK;
                 },
-                Syscall::GetTime => {
-                    // gettime
-                    (*frame).regs[Registers::A0 as usize] = crate::cpu::get_mtime();
-                    0
+                FileDescriptor::Pipe(end) => {
+                    end.flags = end.flags & !fs::O_NONBLOCK | arg & fs::O_NONBLOCK;
                 },
+                _ => {},
             }
+            0
         },
-    )
+        _ => return Err(Errno::EINVAL),
+    })
+}
+
+unsafe fn sys_exit(frame: *mut TrapFrame, status: usize) -> Resume {
+    // Only the low byte makes it to the parent.
+    with_table(|table| table.exit((*frame).pid as u16, (status & 0xff) << 8));
+    Resume::Schedule
+}
+
+/// A pid of 0 means our own process group and -pgid another one. We
+/// don't do -1, which is everyone.
+unsafe fn sys_kill(frame: *mut TrapFrame, pid: isize, sig: usize) -> Result<usize, Errno> {
+    if (sig != 0 && !is_valid(sig)) || pid == -1 {
+        return Err(Errno::EINVAL);
+    }
+    // Anything that isn't a pid or a process group can't be anybody.
+    let sent = match pid {
+        0 => send_group_signal((*current(frame)?).pgid, sig),
+        _ if pid < 0 => {
+            let pgid = pid
+                .checked_neg()
+                .and_then(|pgid| u16::try_from(pgid).ok())
+                .ok_or(Errno::ESRCH)?;
+            send_group_signal(pgid, sig)
+        },
+        _ => send_signal(u16::try_from(pid).map_err(|_| Errno::ESRCH)?, sig),
+    };
+    if sent { Ok(0) } else { Err(Errno::ESRCH) }
+}
+
+unsafe fn sys_rt_sigaction(
+    frame: *mut TrapFrame,
+    sig: usize,
+    act: usize,
+    oldact: usize,
+    sigsetsize: usize,
+) -> Result<usize, Errno> {
+    if !is_valid(sig) || sigsetsize != size_of::<u64>() {
+        return Err(Errno::EINVAL);
+    }
+    let p = current(frame)?;
+    let old = *(*p).signals.action(sig);
+    let new = if act == 0 {
+        None
+    } else {
+        Some(read_user::<SigAction>(frame, act)?)
+    };
+    if oldact != 0 {
+        write_user(frame, oldact, &old)?;
+    }
+    if new.map_or(true, |new| (*p).signals.set_action(sig, new)) {
+        Ok(0)
+    } else {
+        Err(Errno::EINVAL)
+    }
+}
+
+unsafe fn sys_rt_sigprocmask(
+    frame: *mut TrapFrame,
+    how: usize,
+    set: usize,
+    oldset: usize,
+    sigsetsize: usize,
+) -> Result<usize, Errno> {
+    if sigsetsize != size_of::<u64>() {
+        return Err(Errno::EINVAL);
+    }
+    let signals = &mut (*current(frame)?).signals;
+    let old = signals.blocked;
+    let new = if set == 0 {
+        None
+    } else {
+        Some(read_user::<u64>(frame, set)?)
+    };
+    if oldset != 0 {
+        write_user(frame, oldset, &old)?;
+    }
+    match (how, new) {
+        (_, None) => {},
+        (SIG_BLOCK, Some(new)) => signals.set_blocked(old | new),
+        (SIG_UNBLOCK, Some(new)) => signals.set_blocked(old & !new),
+        (SIG_SETMASK, Some(new)) => signals.set_blocked(new),
+        _ => return Err(Errno::EINVAL),
+    }
+    Ok(0)
+}
+
+unsafe fn sys_rt_sigreturn(frame: *mut TrapFrame) -> Resume {
+    // The stack pointer is where deliver left the signal
+    // frame. We go straight back to wherever it says.
+    sigreturn(frame);
+    Resume::At((*frame).pc)
+}
+
+unsafe fn sys_getpid(frame: *mut TrapFrame) -> Result<usize, Errno> {
+    Ok((*frame).pid)
+}
+
+/// A process can move itself or one of its children, and only to a new
+/// group of its own or one that exists.
+unsafe fn sys_setpgid(frame: *mut TrapFrame, pid: usize, pgid: usize) -> Result<usize, Errno> {
+    let me = (*frame).pid as u16;
+    let pid = pid_arg(pid)?;
+    let pgid = pid_arg(pgid).map_err(|_| Errno::EINVAL)?;
+    let pid = if pid == 0 { me } else { pid };
+    let pgid = if pgid == 0 { pid } else { pgid };
+    let p = get_by_pid(pid);
+    if p.is_null() || (pid != me && (*p).ppid != me) {
+        return Err(Errno::ESRCH);
+    }
+    if (*(*p).frame).mode != CpuMode::User as usize || (pgid != pid && !group_exists(pgid)) {
+        return Err(Errno::EPERM);
+    }
+    (*p).pgid = pgid;
+    Ok(0)
+}
+
+unsafe fn sys_getpgid(frame: *mut TrapFrame, pid: usize) -> Result<usize, Errno> {
+    let pid = pid_arg(pid)?;
+    let p = get_by_pid(if pid == 0 { (*frame).pid as u16 } else { pid });
+    if p.is_null() {
+        return Err(Errno::ESRCH);
+    }
+    Ok((*p).pgid as usize)
+}
+
+/// The processes that `which` and `who` of setpriority and getpriority
+/// stand for. A `who` of 0 is ourselves or our own process group. We
+/// have no users, so there's no PRIO_USER.
+fn priority_targets(table: &ProcessTable, me: u16, which: usize, who: u16) -> Result<Vec<u16>, Errno> {
+    let pids: Vec<u16> = match which {
+        PRIO_PROCESS => {
+            let pid = if who == 0 { me } else { who };
+            table
+                .get(pid)
+                .filter(|p| !matches!(p.state, ProcessState::Dead))
+                .map(|p| p.pid)
+                .into_iter()
+                .collect()
+        },
+        PRIO_PGRP => {
+            let pgid = if who == 0 {
+                table.get(me).map_or(0, |p| p.pgid)
+            } else {
+                who
+            };
+            table
+                .iter()
+                .filter(|p| p.pgid == pgid && !matches!(p.state, ProcessState::Dead))
+                .map(|p| p.pid)
+                .collect()
+        },
+        _ => return Err(Errno::EINVAL),
+    };
+    if pids.is_empty() { Err(Errno::ESRCH) } else { Ok(pids) }
+}
+
+/// Anyone can make anyone nicer or less nice, since we have no users to
+/// tell apart. Out of range values are clamped, like Linux does.
+unsafe fn sys_setpriority(frame: *mut TrapFrame, which: usize, who: usize, prio: isize) -> Result<usize, Errno> {
+    let who = pid_arg(who)?;
+    let me = (*frame).pid as u16;
+    let nice = prio.clamp(NICE_MIN as isize, NICE_MAX as isize) as i8;
+    with_table(|table| {
+        for pid in priority_targets(table, me, which, who)? {
+            table.set_nice(pid, nice);
+        }
+        Ok(0)
+    })
+    .unwrap_or(Err(Errno::EAGAIN))
+}
+
+/// Like Linux, this returns 20 - nice, so that it's never negative, and
+/// the C library turns it back. For a group, the highest priority in it
+/// counts.
+unsafe fn sys_getpriority(frame: *mut TrapFrame, which: usize, who: usize) -> Result<usize, Errno> {
+    let who = pid_arg(who)?;
+    let me = (*frame).pid as u16;
+    with_table(|table| {
+        let pids = priority_targets(table, me, which, who)?;
+        let nice = pids.iter().filter_map(|&pid| table.get(pid)).map(|p| p.nice).min();
+        Ok(sched::priority(nice.unwrap_or(0)))
+    })
+    .unwrap_or(Err(Errno::EAGAIN))
+}
+
+/// Switch tracing of our own system calls, or those of a child, on or
+/// off, see [`crate::strace`].
+unsafe fn sys_trace(frame: *mut TrapFrame, pid: usize, flags: usize) -> Result<usize, Errno> {
+    let pid = pid_arg(pid)?;
+    let me = (*frame).pid as u16;
+    let pid = if pid == 0 { me } else { pid };
+    let p = get_by_pid(pid);
+    if p.is_null() || (pid != me && (*p).ppid != me) {
+        return Err(Errno::ESRCH);
+    }
+    if flags & !(TRACE_ON | TRACE_FOLLOW) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    (*p).trace = Trace::from_flags(flags);
+    Ok(0)
+}
+
+/// Power off or reboot. There's no Ctrl-Alt-Del to switch on or off, and
+/// no other command for RESTART2 to pass `arg` to.
+unsafe fn sys_reboot(_frame: *mut TrapFrame, magic1: u32, magic2: u32, cmd: u32, _arg: usize) -> Result<usize, Errno> {
+    if !power::magic_ok(magic1, magic2) {
+        return Err(Errno::EINVAL);
+    }
+    match cmd {
+        LINUX_REBOOT_CMD_POWER_OFF | LINUX_REBOOT_CMD_HALT => power::poweroff(),
+        LINUX_REBOOT_CMD_RESTART | LINUX_REBOOT_CMD_RESTART2 => power::reboot(),
+        LINUX_REBOOT_CMD_CAD_ON | LINUX_REBOOT_CMD_CAD_OFF => Ok(0),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+/// Only the console knows any requests.
+unsafe fn sys_ioctl(frame: *mut TrapFrame, fd: usize, request: usize, argp: usize) -> Result<usize, Errno> {
+    let fd = fd_arg(fd)?;
+    let p = current(frame)?;
+    match (*p).data.fdesc.get(&fd) {
+        Some(FileDescriptor::Device(console::CONSOLE)) => syscall_console_ioctl(frame, request, argp),
+        Some(_) => Err(Errno::ENOTTY),
+        None => Err(Errno::EBADF),
+    }
+}
+
+unsafe fn sys_block_read(frame: *mut TrapFrame, dev: usize, buf: usize, size: usize, offset: u64) -> Resume {
+    if (*frame).satp >> 60 == 0 {
+        // Kernel processes hand us physical memory, which the
+        // block device can write into directly.
+        // Interrupts are off in here, so the request can't
+        // finish before we wait for it.
+        if let Err(error) = block_op(dev, buf as *mut u8, size as u32, offset, false, (*frame).pid as u16) {
+            return Resume::Return(Err(error.into()));
+        }
+        with_table(|table| table.set_state((*frame).pid as u16, ProcessState::Waiting));
+    } else {
+        if let Err(error) = access_ok(frame, buf, size, true) {
+            return Resume::Return(Err(error.into()));
+        }
+        // A user buffer may be scattered over physical memory,
+        // so it goes through a kernel bounce buffer instead.
+        block::process_read((*frame).pid as u16, dev, buf, size as u32, offset);
+    }
+    Resume::Schedule
+}
+
+unsafe fn sys_brk(frame: *mut TrapFrame, addr: usize) -> Result<usize, Errno> {
+    Ok((*current(frame)?).set_brk(addr))
+}
+
+/// `length` rounded up to whole pages, or None if no mapping could be that
+/// long. Rounding up something that large would overflow.
+fn page_len(length: usize) -> Option<usize> {
+    if length > MMAP_END {
+        None
+    } else {
+        Some(align_val(length, 12))
+    }
+}
+
+unsafe fn sys_munmap(frame: *mut TrapFrame, addr: usize, length: usize) -> Result<usize, Errno> {
+    let len = page_len(length).ok_or(Errno::EINVAL)?;
+    if addr % PAGE_SIZE != 0 || len == 0 {
+        return Err(Errno::EINVAL);
+    }
+    (*current(frame)?).unmap_area(addr, addr.saturating_add(len));
+    Ok(0)
+}
+
+/// 220 is clone in the Linux numbering, but we only do the fork() part
+/// of it: a full copy of the caller.
+unsafe fn sys_fork(frame: *mut TrapFrame) -> Result<usize, Errno> {
+    let parent = current(frame)?;
+    // We're in an interrupt context, so we can't wait for the
+    // process table. If a kernel process has it, or we're out of
+    // pids, the caller can try again later.
+    with_table(|table| table.add(|pid| (*parent).fork(pid)))
+        .flatten()
+        .map(|pid| pid as usize)
+        .ok_or(Errno::EAGAIN)
+}
+
+/// Which children a pid waits for is up to
+/// [`crate::process::ProcessTable::find_child`]. There's no resource usage
+/// to fill in.
+unsafe fn sys_wait4(frame: *mut TrapFrame, pid: isize, status: usize, options: usize, _rusage: usize) -> Resume {
+    let me = (*frame).pid as u16;
+    let found = with_table(|table| table.find_child(me, pid)).unwrap_or(ChildStatus::NoChildren);
+    Resume::Return(match found {
+        ChildStatus::Exited(child, exit_status) => {
+            if status != 0 && write_user(frame, status, &(exit_status as u32)).is_err() {
+                Err(Errno::EFAULT)
+            } else {
+                // The status has been collected, so the zombie can go.
+                // Its times are ours now.
+                if let Some(zombie) = with_table(|table| table.remove(child)).flatten() {
+                    with_table(|table| table.get_mut(me).map(|p| p.times.reap(&zombie.times)));
+                }
+                Ok(child as usize)
+            }
+        },
+        ChildStatus::Running if options & WNOHANG != 0 => Ok(0),
+        ChildStatus::Running => {
+            // Sleep until one of our children exits, and then
+            // make the same system call again to collect it.
+            return block_and_restart(frame);
+        },
+        ChildStatus::NoChildren => Err(Errno::ECHILD),
+    })
+}
+
+/// Anonymous private mappings get zeroed pages, file mappings get a
+/// private, read-only copy of the file that a kernel process reads in
+/// before we return.
+unsafe fn sys_mmap(
+    frame: *mut TrapFrame,
+    addr: usize,
+    length: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> Result<usize, Errno> {
+    let len = page_len(length).ok_or(Errno::ENOMEM)?;
+    let p = current(frame)?;
+    if len == 0 ||
+        offset % PAGE_SIZE != 0 ||
+        flags & (MAP_SHARED | MAP_PRIVATE) == 0 ||
+        prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0
+    {
+        return Err(Errno::EINVAL);
+    }
+    let backing = if flags & MAP_ANONYMOUS != 0 {
+        // We can't keep anonymous memory shared across a fork.
+        if flags & MAP_SHARED != 0 {
+            return Err(Errno::EINVAL);
+        }
+        Backing::Anonymous
+    } else {
+        match (*p).data.fdesc.get(&fd_arg(fd)?) {
+            Some(FileDescriptor::File(f))
+                if f.borrow().flags & fs::O_ACCMODE != fs::O_WRONLY && prot & PROT_WRITE == 0 =>
+            {
+                let f = f.borrow();
+                Backing::File {
+                    bdev: f.bdev,
+                    inode: f.inode,
+                    offset,
+                }
+            },
+            Some(FileDescriptor::File(_)) => return Err(Errno::EACCES),
+            Some(_) => return Err(Errno::EINVAL),
+            None => return Err(Errno::EBADF),
+        }
+    };
+    // A fixed address replaces whatever was mapped there before.
+    // Otherwise the address is only a hint, and we look for room
+    // ourselves if it's taken.
+    let in_range = |start: usize| start % PAGE_SIZE == 0 && start >= MMAP_BASE && start.saturating_add(len) <= MMAP_END;
+    let start = if flags & MAP_FIXED != 0 {
+        if addr % PAGE_SIZE != 0 {
+            return Err(Errno::EINVAL);
+        }
+        if !in_range(addr) {
+            return Err(Errno::ENOMEM);
+        }
+        (*p).unmap_area(addr, addr + len);
+        addr
+    } else if in_range(addr) && (*p).vmas.is_free(addr, addr + len) {
+        addr
+    } else {
+        (*p).vmas.find_free(len).ok_or(Errno::ENOMEM)?
+    };
+    let vma = Vma {
+        start,
+        end: start + len,
+        prot,
+        backing,
+    };
+    if !(*p).map_area(vma) {
+        return Err(Errno::ENOMEM);
+    }
+    if let Backing::File { bdev, inode, offset } = backing {
+        let size = (inode.size as usize).saturating_sub(offset).min(len);
+        if size != 0 {
+            fs::process_map((*frame).pid as u16, bdev, inode, start, size as u32, offset as u32);
+        }
+    }
+    Ok(start)
+}
+
+unsafe fn sys_mprotect(frame: *mut TrapFrame, addr: usize, length: usize, prot: usize) -> Result<usize, Errno> {
+    let len = page_len(length).ok_or(Errno::ENOMEM)?;
+    if addr % PAGE_SIZE != 0 || prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    if !(*current(frame)?).protect_area(addr, addr.saturating_add(len), prot) {
+        return Err(Errno::ENOMEM);
+    }
+    Ok(0)
+}
+
+unsafe fn sys_get_fb(frame: *mut TrapFrame, dev: usize) -> Result<usize, Errno> {
+    if dev == 0 || dev > 8 {
+        return Err(Errno::ENODEV);
+    }
+    let p = gpu::GPU_DEVICES[dev - 1].take().ok_or(Errno::ENODEV)?;
+    let ptr = p.get_framebuffer() as usize;
+    let ret = if (*frame).satp >> 60 == 0 {
+        Ok(ptr)
+    } else {
+        // The framebuffer goes wherever there is room for it, like
+        // any other mapping.
+        let process = current(frame)?;
+        let len = align_val((p.get_width() * p.get_height() * 4) as usize, 12);
+        match (*process).vmas.find_free(len) {
+            Some(start) => {
+                let vma = Vma {
+                    start,
+                    end: start + len,
+                    prot: PROT_READ | PROT_WRITE,
+                    backing: Backing::Device(ptr),
+                };
+                if (*process).map_area(vma) {
+                    Ok(start)
+                } else {
+                    Err(Errno::ENOMEM)
+                }
+            },
+            None => Err(Errno::ENOMEM),
+        }
+    };
+    gpu::GPU_DEVICES[dev - 1].replace(p);
+    ret
+}
+
+unsafe fn sys_inv_rect(_frame: *mut TrapFrame, dev: usize, x: u32, y: u32, width: u32, height: u32) -> Resume {
+    gpu::transfer(dev, x, y, width, height);
+    Resume::Schedule
+}
+
+unsafe fn sys_get_key(frame: *mut TrapFrame, events: usize, max: usize) -> Result<usize, Errno> {
+    let mut ev = KEY_EVENTS.take().ok_or(Errno::ENODEV)?;
+    let ret = copy_events(frame, &mut ev, events, max);
+    KEY_EVENTS.replace(ev);
+    ret
+}
+
+unsafe fn sys_get_abs(frame: *mut TrapFrame, events: usize, max: usize) -> Result<usize, Errno> {
+    let mut ev = ABS_EVENTS.take().ok_or(Errno::ENODEV)?;
+    let ret = copy_events(frame, &mut ev, events, max);
+    ABS_EVENTS.replace(ev);
+    ret
+}
+
+unsafe fn sys_get_time(_frame: *mut TrapFrame) -> Result<usize, Errno> {
+    Ok(crate::cpu::get_mtime())
+}
+
+unsafe fn sys_clock_gettime(frame: *mut TrapFrame, clockid: usize, tp: usize) -> Result<usize, Errno> {
+    let ns = time::clock_ns(clockid).ok_or(Errno::EINVAL)?;
+    write_user(frame, tp, &Timespec::from_ns(ns))?;
+    Ok(0)
+}
+
+/// There are no time zones, so `tz`, if given, gets UTC.
+unsafe fn sys_gettimeofday(frame: *mut TrapFrame, tv: usize, tz: usize) -> Result<usize, Errno> {
+    if tv != 0 {
+        write_user(frame, tv, &Timeval::from_ns(time::realtime_ns()))?;
+    }
+    if tz != 0 {
+        // struct timezone: minutes west of Greenwich, and the type of DST
+        write_user(frame, tz, &[0_i32; 2])?;
+    }
+    Ok(0)
+}
+
+/// Returns the time since boot, in the same units.
+unsafe fn sys_times(frame: *mut TrapFrame, buf: usize) -> Result<usize, Errno> {
+    let p = current(frame)?;
+    if buf != 0 {
+        write_user(frame, buf, &(*p).times.to_tms())?;
+    }
+    Ok(time::ticks_to(crate::cpu::get_mtime() as u64, time::CLK_TCK) as usize)
+}
+
+/// What uname fills in, laid out like Linux' struct new_utsname
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Utsname {
+    sysname: [u8; 65],
+    nodename: [u8; 65],
+    release: [u8; 65],
+    version: [u8; 65],
+    machine: [u8; 65],
+    domainname: [u8; 65],
+}
+
+/// One NUL terminated field of [`Utsname`]
+fn uts_field(value: &str) -> [u8; 65] {
+    let mut field = [0; 65];
+    field[..value.len()].copy_from_slice(value.as_bytes());
+    field
+}
+
+unsafe fn sys_uname(frame: *mut TrapFrame, buf: usize) -> Result<usize, Errno> {
+    let uts = Utsname {
+        sysname: uts_field(env!("CARGO_PKG_NAME")),
+        nodename: uts_field(env!("CARGO_PKG_NAME")),
+        release: uts_field(env!("CARGO_PKG_VERSION")),
+        version: uts_field("#1"),
+        machine: uts_field("riscv64"),
+        domainname: uts_field("(none)"),
+    };
+    write_user(frame, buf, &uts)?;
+    Ok(0)
+}
+
+/// Whether any process is in process group `pgid`. If we can't get the
+/// process table, we can't tell, and say no.
+fn group_exists(pgid: u16) -> bool {
+    with_table(|table| table.group_exists(pgid)).unwrap_or(false)
+}
+
+/// Put the caller to sleep until something wakes it up with
+/// [`crate::process::ProcessTable::wake_up`], and then have it make the
+/// same system call again.
+unsafe fn block_and_restart(frame: *mut TrapFrame) -> Resume {
+    let p = match current(frame) {
+        Ok(p) => p,
+        Err(error) => return Resume::Return(Err(error)),
+    };
+    (*p).interruptible = true;
+    with_table(|table| table.set_state((*p).pid, ProcessState::Waiting));
+    // m_trap skips the ecall when we schedule, so this puts us right on it.
+    (*frame).pc -= 4;
+    Resume::Schedule
+}
+
+/// read() from a pipe. An empty pipe blocks until someone writes to it
+/// or the last writer closes it, unless it's in non-blocking mode.
+unsafe fn syscall_pipe_read(frame: *mut TrapFrame, end: &PipeEnd, buffer: usize, size: usize) -> Resume {
+    let size = size.min(PIPE_SIZE);
+    // Check the buffer before taking anything out of the pipe, the data
+    // would be lost otherwise.
+    if let Err(error) = access_ok(frame, buffer, size, true) {
+        return Resume::Return(Err(error.into()));
+    }
+    let mut data = vec![0; size];
+    Resume::Return(match end.read(&mut data) {
+        PipeResult::Done(count) => {
+            let _ = copy_to_user(frame, buffer, data.as_ptr(), count);
+            Ok(count)
+        },
+        PipeResult::WouldBlock if end.flags & fs::O_NONBLOCK == 0 => {
+            end.wait((*frame).pid as u16);
+            return block_and_restart(frame);
+        },
+        _ => Err(Errno::EAGAIN),
+    })
+}
+
+/// The process making the system call. It's in the table for as long as
+/// it is, but if a kernel process has the table, the caller has to try
+/// again.
+unsafe fn current(frame: *const TrapFrame) -> Result<*mut Process, Errno> {
+    let p = get_by_pid((*frame).pid as u16);
+    if p.is_null() { Err(Errno::EAGAIN) } else { Ok(p) }
+}
+
+/// `fd` as a number in the descriptor table, or EBADF if it's too big to
+/// be one.
+fn fd_arg(fd: usize) -> Result<u16, Errno> {
+    if fd < OPEN_MAX {
+        Ok(fd as u16)
+    } else {
+        Err(Errno::EBADF)
+    }
+}
+
+/// `pid` as a pid, or ESRCH if no process can have it.
+fn pid_arg(pid: usize) -> Result<u16, Errno> {
+    if pid < PID_MAX {
+        Ok(pid as u16)
+    } else {
+        Err(Errno::ESRCH)
+    }
+}
+
+/// Copy the path at `v_addr` from the user and make it absolute. A
+/// relative path starts at the directory open as `dirfd`, or at our
+/// current directory if that is AT_FDCWD.
+unsafe fn user_path(frame: *mut TrapFrame, dirfd: usize, v_addr: usize) -> Result<String, Errno> {
+    let path = match copy_string_from_user(frame, v_addr) {
+        Ok(path) => path,
+        Err(AccessError::TooLong(_)) => return Err(Errno::ENAMETOOLONG),
+        Err(error) => return Err(error.into()),
+    };
+    let p = current(frame)?;
+    let dir = if path.starts_with('/') || dirfd as isize == fs::AT_FDCWD {
+        String::from((*p).data.cwd())
+    } else {
+        match (*p).data.fdesc.get(&fd_arg(dirfd)?) {
+            Some(FileDescriptor::File(file)) if file.borrow().inode.mode & fs::S_IFDIR != 0 => {
+                file.borrow().path.clone()
+            },
+            Some(_) => return Err(Errno::ENOTDIR),
+            None => return Err(Errno::EBADF),
+        }
+    };
+    Ok(fs::resolve_path(&dir, &path))
+}
+
+/// Stat what `fd` refers to. Only files live on a device. Pipes and the
+/// console only tell what they are, so that isatty can tell them apart.
+unsafe fn fd_stat(frame: *mut TrapFrame, fd: u16) -> Result<Stat, Errno> {
+    let p = current(frame)?;
+    let mode = match (*p).data.fdesc.get(&fd) {
+        Some(FileDescriptor::File(file)) => {
+            let file = file.borrow();
+            let inode = fs::MinixFileSystem::cached_inode(file.bdev, file.ino).unwrap_or(file.inode);
+            return Ok(fs::MinixFileSystem::stat(file.bdev, file.ino, &inode));
+        },
+        Some(FileDescriptor::Pipe(_)) => fs::S_IFIFO | 0o600,
+        Some(FileDescriptor::Device(console::CONSOLE)) => fs::S_IFCHR | 0o620,
+        _ => return Err(Errno::EBADF),
+    };
+    Ok(Stat {
+        mode: mode as u32,
+        nlink: 1,
+        blksize: PAGE_SIZE as i32,
+        ..Stat::default()
+    })
+}
+
+/// Stat the file at `path`, which is absolute.
+fn path_stat(path: &str) -> Result<Stat, Errno> {
+    let (ino, inode) = fs::MinixFileSystem::lookup(8, path)?;
+    Ok(fs::MinixFileSystem::stat(8, ino, &inode))
+}
+
+/// read() from the console. This blocks until there is something to
+/// read, which in canonical mode is a whole line.
+unsafe fn syscall_console_read(frame: *mut TrapFrame, buffer: usize, size: usize) -> Resume {
+    let size = size.min(PAGE_SIZE);
+    if let Err(error) = access_ok(frame, buffer, size, true) {
+        return Resume::Return(Err(error.into()));
+    }
+    // With nobody in the foreground, the first one to read takes the
+    // console, like a shell does when it starts.
+    let p = match current(frame) {
+        Ok(p) => p,
+        Err(error) => return Resume::Return(Err(error)),
+    };
+    if console::foreground() == 0 || !group_exists(console::foreground()) {
+        console::set_foreground((*p).pgid);
+    }
+    let mut data = vec![0; size];
+    let count = match console::read(&mut data) {
+        Some(count) => count,
+        None => {
+            console::wait((*frame).pid as u16);
+            return block_and_restart(frame);
+        },
+    };
+    let _ = copy_to_user(frame, buffer, data.as_ptr(), count);
+    Resume::Return(Ok(count))
+}
+
+/// The termios and process group ioctls on the console
+unsafe fn syscall_console_ioctl(frame: *mut TrapFrame, request: usize, arg: usize) -> Result<usize, Errno> {
+    match request {
+        console::TCGETS => write_user(frame, arg, &console::termios())?,
+        // Output is never held back, so there's nothing to drain for
+        // TCSETSW.
+        console::TCSETS | console::TCSETSW | console::TCSETSF => {
+            let termios = read_user::<Termios>(frame, arg)?;
+            console::set_termios(termios, request == console::TCSETSF);
+        },
+        console::TIOCGPGRP => write_user(frame, arg, &(console::foreground() as i32))?,
+        console::TIOCSPGRP => {
+            let pgrp = read_user::<i32>(frame, arg)?;
+            if pgrp <= 0 || pgrp as usize >= PID_MAX {
+                return Err(Errno::EINVAL);
+            }
+            if !group_exists(pgrp as u16) {
+                return Err(Errno::EPERM);
+            }
+            console::set_foreground(pgrp as u16);
+        },
+        _ => return Err(Errno::ENOTTY),
+    }
+    Ok(0)
+}
+
+/// write() and writev() to `fd`, with `iov` being the user buffers to
+/// write one after the other as (address, length). We gather them in a
+/// kernel buffer first, so that they go out in one piece. A write that is
+/// too long comes back short, and the caller has to write the rest.
+unsafe fn syscall_write(frame: *mut TrapFrame, fd: u16, iov: &[(usize, usize)]) -> Resume {
+    let p = match current(frame) {
+        Ok(p) => p,
+        Err(error) => return Resume::Return(Err(error)),
+    };
+    let limit = match (*p).data.fdesc.get(&fd) {
+        Some(FileDescriptor::Pipe(_)) => PIPE_SIZE,
+        _ => MAX_WRITE,
+    };
+    let mut data = Vec::new();
+    for &(v_addr, len) in iov {
+        let start = data.len();
+        let len = len.min(limit - start);
+        data.resize(start + len, 0);
+        if let Err(error) = copy_from_user(frame, data[start..].as_mut_ptr(), v_addr, len) {
+            return Resume::Return(Err(error.into()));
+        }
+    }
+    // Files are opened read-only since the Minix driver can't write yet,
+    // so only pipes and the console can be written to.
+    match (*p).data.fdesc.get(&fd) {
+        Some(FileDescriptor::Pipe(end)) if end.flags & fs::O_ACCMODE != fs::O_RDONLY => {
+            syscall_pipe_write(frame, end, &data)
+        },
+        Some(FileDescriptor::Device(console::CONSOLE)) => {
+            console::write(&data);
+            Resume::Return(Ok(data.len()))
+        },
+        _ => Resume::Return(Err(Errno::EBADF)),
+    }
+}
+
+/// write() to a pipe. This writes as much as fits and returns how much
+/// that was, and only blocks if nothing fits at all. Writing to a pipe
+/// without readers fails, and sends us a SIGPIPE too.
+unsafe fn syscall_pipe_write(frame: *mut TrapFrame, end: &PipeEnd, data: &[u8]) -> Resume {
+    Resume::Return(match end.write(data) {
+        PipeResult::Done(count) => Ok(count),
+        PipeResult::WouldBlock if end.flags & fs::O_NONBLOCK == 0 => {
+            end.wait((*frame).pid as u16);
+            return block_and_restart(frame);
+        },
+        PipeResult::WouldBlock => Err(Errno::EAGAIN),
+        PipeResult::Broken => {
+            send_signal((*frame).pid as u16, SIGPIPE);
+            Err(Errno::EPIPE)
+        },
+    })
+}
+
+/// Hand as many queued input events as fit into the user's array at
+/// `v_addr` (`max` entries long) and return how many were copied. Events
+/// stay queued if the array isn't writable.
+unsafe fn copy_events(
+    frame: *mut TrapFrame,
+    ev: &mut VecDeque<Event>,
+    v_addr: usize,
+    max: usize,
+) -> Result<usize, Errno> {
+    let count = max.min(ev.len());
+    access_ok(frame, v_addr, count * size_of::<Event>(), true)?;
+    for i in 0..count {
+        let event = ev.pop_front().unwrap();
+        let _ = write_user(frame, v_addr + i * size_of::<Event>(), &event);
+    }
+    Ok(count)
 }
 
 /// Extern assembly function that correlates registers to proper
@@ -413,25 +1642,12 @@ pub fn syscall_exit() {
 }
 
 /// Overlay Calling Process and Run New Program
-pub fn syscall_execv(path: *const u8, argv: usize) -> usize {
-    do_make_syscall(Syscall::Execv.into(), path as usize, argv, 0, 0, 0, 0)
-}
-
-/// Read file in file system
-pub fn syscall_fs_read(dev: usize, inode: u32, buffer: *mut u8, size: u32, offset: u32) -> usize {
-    do_make_syscall(
-        Syscall::Read.into(),
-        dev,
-        inode as usize,
-        buffer as usize,
-        size as usize,
-        offset as usize,
-        0,
-    )
+pub fn syscall_execv(path: *const u8, argv: usize, envp: usize) -> usize {
+    do_make_syscall(Syscall::Execv.into(), path as usize, argv, envp, 0, 0, 0)
 }
 
 /// Read the block on device
-pub fn syscall_block_read(dev: usize, buffer: *mut u8, size: u32, offset: u32) -> u8 {
+pub fn syscall_block_read(dev: usize, buffer: *mut u8, size: u32, offset: u32) -> usize {
     do_make_syscall(
         Syscall::BlockRead.into(),
         dev,
@@ -440,7 +1656,7 @@ pub fn syscall_block_read(dev: usize, buffer: *mut u8, size: u32, offset: u32) -
         offset as usize,
         0,
         0,
-    ) as u8
+    )
 }
 
 /// Gives a little sleep to the process
@@ -455,31 +1671,80 @@ pub fn syscall_get_pid() -> u16 {
     do_make_syscall(Syscall::GetPid.into(), 0, 0, 0, 0, 0, 0) as u16
 }
 
+/// Wait for a child to exit and return its pid, or 0 if `options`
+/// has [`WNOHANG`] and none has exited yet.
+pub fn syscall_wait4(pid: isize, status: *mut u32, options: usize) -> usize {
+    do_make_syscall(Syscall::Wait4.into(), pid as usize, status as usize, options, 0, 0, 0)
+}
+
+/// What execv hands over to [`exec_func`]
+struct ExecArgs {
+    /// The process whose program gets replaced
+    pid: u16,
+    /// The ELF file to load
+    inode: fs::Inode,
+    /// Arguments for the new program
+    argv: Vec<String>,
+    /// Environment for the new program
+    envp: Vec<String>,
+}
+
 /// This is a helper function ran as a process in kernel space
 /// to finish loading and executing a process.
 fn exec_func(args: usize) {
     unsafe {
-        // We got the inode from the syscall. Its Box rid itself of control, so
-        // we take control back here. The Box now owns the Inode and will complete
+        // We got the arguments from the syscall. Its Box rid itself of control, so
+        // we take control back here. The Box now owns the ExecArgs and will complete
         // freeing the heap memory allocated for it.
-        let inode = Box::from_raw(args as *mut fs::Inode);
+        let args = Box::from_raw(args as *mut ExecArgs);
+        let inode = &args.inode;
         let mut buffer = Buffer::with_capacity(inode.size as usize);
         // This is why we need to be in a process context. The read() call may sleep as it
         // waits for the block driver to return.
-        fs::MinixFileSystem::read(8, &inode, buffer.as_mut_ptr(), inode.size, 0);
-        // Now we have the data, so the following will load the ELF file and give us a process.
-        let proc = elf::File::load_proc(&buffer);
-        if proc.is_err() {
-            println!("Failed to launch process.");
-        } else {
-            // If we hold this lock, we can still be preempted, but the scheduler will
-            // return control to us. This required us to use try_lock in the scheduler.
-            PROCESS_LIST_MUTEX.sleep_lock();
-            if let Some(mut proc_list) = PROCESS_LIST.take() {
-                proc_list.push_back(proc.ok().unwrap());
-                PROCESS_LIST.replace(proc_list);
+        fs::MinixFileSystem::read(8, inode, buffer.as_mut_ptr(), inode.size, 0);
+        // Now we have the data, so the following will load the ELF file and give us a process
+        // that already has the pid of the one it replaces.
+        let proc = elf::File::load_proc(&buffer, args.pid);
+        let mut closed = Vec::new();
+        with_table(|table| {
+            let old = match table.get_mut(args.pid) {
+                Some(old) => old,
+                None => return,
+            };
+            // The new program takes over the old one's identity: its pid, its
+            // parent and its open files. Dropping the old process frees
+            // everything that belonged to the program it ran before.
+            let proc = proc.map_err(Errno::from).and_then(|mut new| {
+                new.data = mem::take(&mut old.data);
+                if new.set_args(&args.argv, &args.envp) {
+                    Ok(new)
+                } else {
+                    old.data = mem::take(&mut new.data);
+                    Err(Errno::E2BIG)
+                }
+            });
+            match proc {
+                Ok(mut new) => {
+                    new.ppid = old.ppid;
+                    new.pgid = old.pgid;
+                    new.signals = old.signals.clone();
+                    new.signals.exec();
+                    new.trace = old.trace.take();
+                    new.times = old.times;
+                    new.nice = old.nice;
+                    closed = new.data.take_cloexec_fds();
+                    *old = new;
+                    satp_fence_asid(args.pid as usize);
+                },
+                Err(errno) => {
+                    // execv returns the error
+                    (*old.frame).regs[Registers::A0 as usize] = errno.to_ret();
+                },
             }
-            PROCESS_LIST_MUTEX.unlock();
-        }
+            // Either way, the caller of execv runs again.
+            table.set_state(args.pid, ProcessState::Running);
+        });
+        // Like in ProcessTable::exit, closing pipes needs the process table.
+        drop(closed);
     }
 }
@@ -9,6 +9,6 @@ pub fn test() {
     MinixFileSystem::init(8);
     // let path = "/pong.elf\0".as_bytes().as_ptr();
     let path = b"/shell.elf\0".as_ptr();
-    syscall::syscall_execv(path, 0);
+    syscall::syscall_execv(path, 0, 0);
     println!("I should never get here, execv should destroy our process.");
 }
@@ -0,0 +1,184 @@
+//! # Time
+//! mtime counts up at [`FREQ`] from the moment we boot, and every clock
+//! here is worked out from it: the ones clock_gettime and gettimeofday
+//! read, and the user and system time that times() hands out for every
+//! process. The realtime clock is mtime plus the date at boot, which
+//! comes from the RTC.
+//!
+//! Time is charged to processes on the way into and out of the trap
+//! handler. Whatever ran until a trap came in gets the time since the last
+//! switch, as user time if it ran in user mode. The time spent in the
+//! trap handler itself goes to the process that trapped, as system time.
+use crate::{
+    cpu::{
+        get_mtime,
+        CpuMode,
+        TrapFrame,
+        FREQ,
+    },
+    process::{
+        get_by_pid,
+        with_table,
+    },
+};
+
+// Clocks for clock_gettime, the same as Linux uses
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+/// What times() counts in. newlib takes this to be CLOCKS_PER_SEC.
+pub const CLK_TCK: u64 = 1000;
+
+/// struct timespec
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Timespec {
+    pub sec: i64,
+    pub nsec: i64,
+}
+
+impl Timespec {
+    pub const fn from_ns(ns: u64) -> Self {
+        Self {
+            sec: (ns / NSEC_PER_SEC) as i64,
+            nsec: (ns % NSEC_PER_SEC) as i64,
+        }
+    }
+}
+
+/// struct timeval
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Timeval {
+    pub sec: i64,
+    pub usec: i64,
+}
+
+impl Timeval {
+    pub const fn from_ns(ns: u64) -> Self {
+        Self {
+            sec: (ns / NSEC_PER_SEC) as i64,
+            usec: (ns % NSEC_PER_SEC / 1000) as i64,
+        }
+    }
+}
+
+/// struct tms, what times() fills in, in [`CLK_TCK`]s
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Tms {
+    pub utime: i64,
+    pub stime: i64,
+    pub cutime: i64,
+    pub cstime: i64,
+}
+
+/// The time a process has spent running, in mtime ticks
+#[derive(Copy, Clone, Default)]
+pub struct Times {
+    pub utime: u64,
+    pub stime: u64,
+    /// What the children we collected with wait4 spent, along with
+    /// what their own children did
+    pub cutime: u64,
+    pub cstime: u64,
+}
+
+impl Times {
+    /// Take in the times of a child whose exit status we collected.
+    pub fn reap(&mut self, child: &Self) {
+        self.cutime += child.utime + child.cutime;
+        self.cstime += child.stime + child.cstime;
+    }
+
+    pub const fn to_tms(&self) -> Tms {
+        Tms {
+            utime: ticks_to(self.utime, CLK_TCK) as i64,
+            stime: ticks_to(self.stime, CLK_TCK) as i64,
+            cutime: ticks_to(self.cutime, CLK_TCK) as i64,
+            cstime: ticks_to(self.cstime, CLK_TCK) as i64,
+        }
+    }
+}
+
+/// Turn mtime `ticks` into units of which there are `per_sec` in a
+/// second. Seconds and what is left over are done separately, so that
+/// nothing overflows.
+pub const fn ticks_to(ticks: u64, per_sec: u64) -> u64 {
+    ticks / FREQ * per_sec + ticks % FREQ * per_sec / FREQ
+}
+
+/// Nanoseconds since we booted
+pub fn monotonic_ns() -> u64 {
+    ticks_to(get_mtime() as u64, NSEC_PER_SEC)
+}
+
+/// How far the epoch is before boot, in nanoseconds. Until the RTC tells
+/// us the date, the epoch is when we booted.
+static mut REALTIME_OFFSET: u64 = 0;
+
+/// Nanoseconds since the epoch
+pub fn realtime_ns() -> u64 {
+    monotonic_ns() + unsafe { REALTIME_OFFSET }
+}
+
+/// Seconds since the epoch, the way inodes keep their times
+pub fn realtime_secs() -> u32 {
+    (realtime_ns() / NSEC_PER_SEC) as u32
+}
+
+/// It is `now` nanoseconds since the epoch.
+pub fn set_realtime(now: u64) {
+    unsafe {
+        REALTIME_OFFSET = now.saturating_sub(monotonic_ns());
+    }
+}
+
+/// What clock `clockid` reads, in nanoseconds, or None for a clock that
+/// we don't have
+pub fn clock_ns(clockid: usize) -> Option<u64> {
+    match clockid {
+        CLOCK_REALTIME => Some(realtime_ns()),
+        CLOCK_MONOTONIC => Some(monotonic_ns()),
+        _ => None,
+    }
+}
+
+/// When we last came into or left the trap handler
+static mut LAST_SWITCH: usize = 0;
+/// Who the time spent in the trap handler goes to
+static mut TRAPPED: u16 = 0;
+
+unsafe fn charge(pid: u16, ticks: usize, user: bool) {
+    if let Some(p) = get_by_pid(pid).as_mut() {
+        if user {
+            p.times.utime += ticks as u64;
+        } else {
+            p.times.stime += ticks as u64;
+        }
+    }
+}
+
+/// The process that owns `frame` trapped into the kernel. Kernel
+/// processes only ever have system time.
+pub unsafe fn enter_kernel(frame: *const TrapFrame) {
+    let now = get_mtime();
+    let pid = (*frame).pid as u16;
+    let ran = now.wrapping_sub(LAST_SWITCH);
+    charge(pid, ran, (*frame).mode == CpuMode::User as usize);
+    // This is the time the scheduler goes by. If a kernel process was busy
+    // with the process table, it runs for free.
+    with_table(|table| table.charge(pid, ran as u64));
+    LAST_SWITCH = now;
+    TRAPPED = pid;
+}
+
+/// We're about to go back to a process, which may not be the one that
+/// trapped.
+pub unsafe fn leave_kernel() {
+    let now = get_mtime();
+    charge(TRAPPED, now.wrapping_sub(LAST_SWITCH), false);
+    LAST_SWITCH = now;
+    TRAPPED = 0;
+}
@@ -11,10 +11,24 @@ use crate::{
         CONTEXT_SWITCH_TIME,
     },
     plic,
-    process::delete_process,
+    process::get_by_pid,
     rust_switch_to_user,
     sched::schedule,
+    signal::{
+        deliver,
+        force_signal,
+        sigreturn,
+        SIGILL,
+        SIGRETURN_ADDR,
+        SIGSEGV,
+    },
     syscall::do_syscall,
+    time,
+    vma::{
+        PROT_EXEC,
+        PROT_READ,
+        PROT_WRITE,
+    },
 };
 
 // #[derive(TryFromPrimitive)]
@@ -47,6 +61,7 @@ extern "C" fn m_trap(
     _status: usize,
     frame: *mut TrapFrame,
 ) -> usize {
+    unsafe { time::enter_kernel(frame) };
     // We're going to handle all traps in machine mode. RISC-V lets
     // us delegate to supervisor mode, but switching out SATP (virtual memory)
     // gets hairy.
@@ -69,10 +84,12 @@ extern "C" fn m_trap(
                 // process to run.
                 // Machine timer
                 let new_frame = schedule();
-                schedule_next_context_switch(1);
                 if new_frame != 0 {
                     rust_switch_to_user(new_frame);
                 }
+                // A kernel process has the process table, so it goes on
+                // until it's done with it.
+                schedule_next_context_switch(1);
             },
             11 => {
                 // Machine external (interrupt from Platform Interrupt Controller (PLIC))
@@ -92,27 +109,21 @@ extern "C" fn m_trap(
         match cause_num {
             2 => unsafe {
                 // Illegal instruction
-                println!("Illegal instruction CPU#{} -> 0x{:08x}: 0x{:08x}\n", hart, epc, tval);
-                // We need while trues here until we have a functioning "delete from scheduler"
-                // I use while true because Rust will warn us that it looks stupid.
-                // This is what I want so that I remember to remove this and replace
-                // them later.
-                delete_process((*frame).pid as u16);
-                let frame = schedule();
-                schedule_next_context_switch(1);
-                rust_switch_to_user(frame);
+                // The process can catch this. If it doesn't, it dies of it
+                // once we're on the way back out.
+                if force_signal((*frame).pid as u16, SIGILL) {
+                    println!("Illegal instruction CPU#{} -> 0x{:08x}: 0x{:08x}\n", hart, epc, tval);
+                }
             },
             7 => unsafe {
-                println!(
-                    "Error with pid {}, at PC 0x{:08x}, mepc 0x{:08x}",
-                    (*frame).pid,
-                    (*frame).pc,
-                    epc
-                );
-                delete_process((*frame).pid as u16);
-                let frame = schedule();
-                schedule_next_context_switch(1);
-                rust_switch_to_user(frame);
+                if force_signal((*frame).pid as u16, SIGSEGV) {
+                    println!(
+                        "Error with pid {}, at PC 0x{:08x}, mepc 0x{:08x}",
+                        (*frame).pid,
+                        (*frame).pc,
+                        epc
+                    );
+                }
             },
             8 | 9 | 11 => unsafe {
                 // Environment (system) call from User, Supervisor, and Machine modes
@@ -123,34 +134,39 @@ extern "C" fn m_trap(
                     // the system call so that when we resume this process, we're after the ecall.
                     (*frame).pc += 4;
                     let frame = schedule();
-                    schedule_next_context_switch(1);
                     rust_switch_to_user(frame);
                 }
             },
             // Page faults
-            12 => unsafe {
-                // Instruction page fault
-                println!("Instruction page fault CPU#{} -> 0x{:08x}: 0x{:08x}", hart, epc, tval);
-                delete_process((*frame).pid as u16);
-                let frame = schedule();
-                schedule_next_context_switch(1);
-                rust_switch_to_user(frame);
-            },
-            13 => unsafe {
-                // Load page fault
-                println!("Load page fault CPU#{} -> 0x{:08x}: 0x{:08x}", hart, epc, tval);
-                delete_process((*frame).pid as u16);
-                let frame = schedule();
-                schedule_next_context_switch(1);
-                rust_switch_to_user(frame);
-            },
-            15 => unsafe {
-                // Store page fault
-                println!("Store page fault CPU#{} -> 0x{:08x}: 0x{:08x}", hart, epc, tval);
-                delete_process((*frame).pid as u16);
-                let frame = schedule();
-                schedule_next_context_switch(1);
-                rust_switch_to_user(frame);
+            12 | 13 | 15 => unsafe {
+                // Instruction, load and store page faults. Inside one of
+                // the process' areas, this only means that the page hasn't
+                // been touched yet or is shared copy-on-write, so we fix
+                // that up and run the instruction again. Anywhere else, the
+                // process gets a SIGSEGV.
+                let (kind, prot) = match cause_num {
+                    12 => ("Instruction", PROT_EXEC),
+                    13 => ("Load", PROT_READ),
+                    _ => ("Store", PROT_WRITE),
+                };
+                let p = get_by_pid((*frame).pid as u16);
+                if cause_num == 12 && tval == SIGRETURN_ADDR {
+                    // A signal handler returned, see signal::deliver.
+                    sigreturn(frame);
+                    return_pc = (*frame).pc;
+                } else if p.is_null() || !(*p).handle_fault(tval, prot) {
+                    // Only say something if nothing is going to catch it.
+                    if force_signal((*frame).pid as u16, SIGSEGV) {
+                        println!(
+                            "{} page fault CPU#{} pid {} -> 0x{:08x}: bad address 0x{:08x}",
+                            kind,
+                            hart,
+                            (*frame).pid,
+                            epc,
+                            tval
+                        );
+                    }
+                }
             },
             _ => {
                 panic!(
@@ -160,8 +176,18 @@ extern "C" fn m_trap(
             },
         }
     };
-    // Finally, return the updated program counter
-    return_pc
+    // Before going back, act on any signal that came in for whoever we
+    // go back to. That may send it off to a handler, or kill it.
+    unsafe {
+        (*frame).pc = return_pc;
+        if !deliver(frame) {
+            let frame = schedule();
+            rust_switch_to_user(frame);
+        }
+        time::leave_kernel();
+        // Finally, return the updated program counter
+        (*frame).pc
+    }
 }
 
 pub const MMIO_MTIMECMP: *mut u64 = 0x0200_4000_usize as *mut u64;
@@ -0,0 +1,218 @@
+//! # User memory access
+//! System calls are handed virtual addresses of the calling process, but we
+//! run the trap handler in machine mode with the MMU off. Everything a
+//! syscall reads from or writes to user memory has to go through here, so
+//! that every page a buffer spans is translated and checked for the right
+//! permissions before we touch a single byte of it.
+//!
+//! Kernel processes run in machine mode without translation, so when the
+//! trap frame has no MMU mode set, addresses are taken as physical.
+//!
+//! The MMU never sees our accesses, so pages that aren't there yet or are
+//! shared copy-on-write wouldn't fault here. Instead, we hand those to
+//! the process' fault handler first, just like the trap handler would.
+use alloc::{
+    string::String,
+    vec::Vec,
+};
+
+use crate::{
+    cpu::{
+        memcpy,
+        TrapFrame,
+    },
+    page::{
+        virt_to_phys_bits,
+        EntryBits,
+        Table,
+        PAGE_SIZE,
+    },
+    process::get_by_pid,
+    vma::{
+        PROT_READ,
+        PROT_WRITE,
+    },
+};
+
+/// The longest string (including its NUL) we are willing to copy
+/// out of a process, such as a path or an argv entry.
+pub const MAX_STRING: usize = 4096;
+/// The most strings we copy out of a NULL-terminated array of them,
+/// such as argv or envp.
+pub const MAX_STRINGS: usize = 256;
+
+/// Why a user memory access was refused. Each variant carries the
+/// virtual address that failed.
+pub enum AccessError {
+    /// The page isn't mapped at all
+    Unmapped(usize),
+    /// The page is mapped, but not readable/writable from user mode
+    Permission(usize),
+    /// A string didn't have a NUL within [`MAX_STRING`] bytes, or an
+    /// array of them had no NULL within [`MAX_STRINGS`] entries
+    TooLong(usize),
+}
+
+/// Get the root page table of whoever owns `frame`, or None if that
+/// process runs without the MMU.
+pub unsafe fn user_table(frame: *const TrapFrame) -> Option<&'static mut Table> {
+    let satp = (*frame).satp;
+    if satp >> 60 == 0 {
+        None
+    } else {
+        // The PPN is the bottom 44 bits of SATP, see build_satp.
+        (((satp & 0xfff_ffff_ffff) << 12) as *mut Table).as_mut()
+    }
+}
+
+/// Whether the MMU takes `v_addr` as an address at all. In Sv39, bits
+/// 63 to 39 have to be copies of bit 38, but the page walk in software
+/// never looks at them.
+const fn canonical(v_addr: usize) -> bool {
+    ((v_addr << 25) as isize >> 25) as usize == v_addr
+}
+
+/// Translate one user address, making sure the leaf has the user bit and
+/// either the read or write bit depending on what we want to do with it.
+/// Anything the MMU would have faulted on goes to the fault handler of
+/// process `pid` first.
+fn translate(table: &mut Table, v_addr: usize, write: bool, pid: usize) -> Result<usize, AccessError> {
+    if !canonical(v_addr) {
+        return Err(AccessError::Unmapped(v_addr));
+    }
+    let needs_fault = match virt_to_phys_bits(table, v_addr) {
+        None => true,
+        Some((_, bits)) => write && bits & EntryBits::CopyOnWrite.val() != 0,
+    };
+    if needs_fault {
+        let prot = if write { PROT_WRITE } else { PROT_READ };
+        let p = unsafe { get_by_pid(pid as u16) };
+        if !p.is_null() {
+            unsafe { (*p).handle_fault(v_addr, prot) };
+        }
+    }
+    let (p_addr, bits) = virt_to_phys_bits(table, v_addr).ok_or(AccessError::Unmapped(v_addr))?;
+    let need = EntryBits::User.val() |
+        if write {
+            EntryBits::Write.val()
+        } else {
+            EntryBits::Read.val()
+        };
+    if bits & need == need {
+        Ok(p_addr)
+    } else {
+        Err(AccessError::Permission(v_addr))
+    }
+}
+
+/// Check that all of `[v_addr, v_addr + len)` may be read (or written if
+/// `write` is set) by the process owning `frame`. Every page in between
+/// is walked, since two neighbouring virtual pages don't have to be mapped
+/// at all, let alone next to each other in physical memory.
+pub unsafe fn access_ok(frame: *const TrapFrame, v_addr: usize, len: usize, write: bool) -> Result<(), AccessError> {
+    if let Some(table) = user_table(frame) {
+        let end = v_addr.checked_add(len).ok_or(AccessError::Unmapped(v_addr))?;
+        let mut addr = v_addr;
+        while addr < end {
+            translate(table, addr, write, (*frame).pid)?;
+            addr = (addr & !(PAGE_SIZE - 1)) + PAGE_SIZE;
+        }
+    }
+    Ok(())
+}
+
+/// Copy `len` bytes at user address `src` into the kernel buffer `dst`.
+pub unsafe fn copy_from_user(frame: *const TrapFrame, dst: *mut u8, src: usize, len: usize) -> Result<(), AccessError> {
+    access_ok(frame, src, len, false)?;
+    match user_table(frame) {
+        None => memcpy(dst, src as *const u8, len),
+        Some(table) => {
+            let mut done = 0;
+            while done < len {
+                let v_addr = src + done;
+                // Copy at most up to the end of this page, the next one
+                // can live anywhere.
+                let chunk = (PAGE_SIZE - v_addr % PAGE_SIZE).min(len - done);
+                let p_addr = translate(table, v_addr, false, (*frame).pid)?;
+                memcpy(dst.add(done), p_addr as *const u8, chunk);
+                done += chunk;
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Copy `len` bytes from the kernel buffer `src` to user address `dst`.
+/// Nothing is written unless the whole destination is writable.
+pub unsafe fn copy_to_user(frame: *const TrapFrame, dst: usize, src: *const u8, len: usize) -> Result<(), AccessError> {
+    access_ok(frame, dst, len, true)?;
+    match user_table(frame) {
+        None => memcpy(dst as *mut u8, src, len),
+        Some(table) => {
+            let mut done = 0;
+            while done < len {
+                let v_addr = dst + done;
+                let chunk = (PAGE_SIZE - v_addr % PAGE_SIZE).min(len - done);
+                let p_addr = translate(table, v_addr, true, (*frame).pid)?;
+                memcpy(p_addr as *mut u8, src.add(done), chunk);
+                done += chunk;
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Read a single value of type `T` from user address `src`.
+pub unsafe fn read_user<T: Copy>(frame: *const TrapFrame, src: usize) -> Result<T, AccessError> {
+    let mut val = core::mem::MaybeUninit::<T>::uninit();
+    copy_from_user(frame, val.as_mut_ptr() as *mut u8, src, core::mem::size_of::<T>())?;
+    Ok(val.assume_init())
+}
+
+/// Write a single value of type `T` to user address `dst`.
+pub unsafe fn write_user<T: Copy>(frame: *const TrapFrame, dst: usize, val: &T) -> Result<(), AccessError> {
+    copy_to_user(frame, dst, val as *const T as *const u8, core::mem::size_of::<T>())
+}
+
+/// Copy a NUL-terminated string out of user space. The NUL isn't part of
+/// the returned string.
+pub unsafe fn copy_string_from_user(frame: *const TrapFrame, src: usize) -> Result<String, AccessError> {
+    let mut table = user_table(frame);
+    let mut ret = String::new();
+    let mut v_addr = src;
+    while v_addr - src < MAX_STRING {
+        // Only translate once per page, and then walk the bytes on it.
+        let p_addr = match table {
+            None => v_addr,
+            Some(ref mut table) => translate(table, v_addr, false, (*frame).pid)?,
+        };
+        let chunk = (PAGE_SIZE - v_addr % PAGE_SIZE).min(MAX_STRING - (v_addr - src));
+        for i in 0..chunk {
+            let ch = *(p_addr as *const u8).add(i);
+            if ch == 0 {
+                return Ok(ret);
+            }
+            ret.push(ch as char);
+        }
+        v_addr = v_addr.checked_add(chunk).ok_or(AccessError::Unmapped(v_addr))?;
+    }
+    Err(AccessError::TooLong(src))
+}
+
+/// Copy a NULL-terminated array of string pointers, like argv, out of
+/// user space.
+pub unsafe fn copy_string_array_from_user(frame: *const TrapFrame, src: usize) -> Result<Vec<String>, AccessError> {
+    let mut ret = Vec::new();
+    for i in 0..MAX_STRINGS {
+        // An array that runs off the end of the address space isn't there.
+        let entry = src
+            .checked_add(i * core::mem::size_of::<usize>())
+            .ok_or(AccessError::Unmapped(src))?;
+        let ptr = read_user::<usize>(frame, entry)?;
+        if ptr == 0 {
+            return Ok(ret);
+        }
+        ret.push(copy_string_from_user(frame, ptr)?);
+    }
+    Err(AccessError::TooLong(src))
+}
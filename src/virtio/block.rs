@@ -2,6 +2,7 @@ use alloc::boxed::Box;
 use core::mem::size_of;
 
 use crate::{
+    errno::Errno,
     kmem::{
         kfree,
         kmalloc,
@@ -13,9 +14,11 @@ use crate::{
     process::{
         add_kernel_process_args,
         get_by_pid,
-        set_running,
-        set_waiting,
+        with_table,
+        ProcessState,
     },
+    syscall::syscall_block_read,
+    uaccess::copy_to_user,
     virtio::{
         self,
         Descriptor,
@@ -24,6 +27,7 @@ use crate::{
         StatusField,
         VIRTIO_RING_SIZE,
     },
+    Buffer,
 };
 
 #[repr(C)]
@@ -197,7 +201,7 @@ pub unsafe fn setup_block_device(ptr: *mut u32) -> bool {
         ptr.add(MmioOffsets::Status.scale32())
             .write_volatile(StatusField::Failed.val32());
         return false;
-    }    // 7. Perform device-specific setup.
+    } // 7. Perform device-specific setup.
     // Set the queue num. We have to make sure that the
     // queue size is valid because the device can only take
     // a certain size.
@@ -207,7 +211,7 @@ pub unsafe fn setup_block_device(ptr: *mut u32) -> bool {
     if VIRTIO_RING_SIZE as u32 > qnmax {
         print!("queue size fail...");
         return false;
-    }    // First, if the block device array is empty, create it!
+    } // First, if the block device array is empty, create it!
     // We add 4095 to round this up and then do an integer
     // divide to truncate the decimal. We don't add 4096,
     // because if it is exactly 4096 bytes, we would get two
@@ -296,7 +300,6 @@ pub fn block_op(
             // Check to see if we are trying to write to a read only
             // device.
             if bdev.read_only && write {
-                println!("Trying to write to read/only!");
                 return Err(BlockErrors::ReadOnly);
             }
             if size % 512 != 0 {
@@ -382,11 +385,14 @@ pub fn pending(bd: &mut BlockDevice) {
             let pid_of_watcher = (*rq).watcher;
             // A PID of 0 means that we don't have a watcher.
             if pid_of_watcher > 0 {
-                set_running(pid_of_watcher);
+                with_table(|table| table.set_state(pid_of_watcher, ProcessState::Running));
                 let proc = get_by_pid(pid_of_watcher);
-                (*(*proc).get_frame_mut()).regs[10] = (*rq).status.status as usize;
-                // TODO: Set GpA0 to the value of the return
-                // status.
+                // The device only tells us whether it worked.
+                (*(*proc).get_frame_mut()).regs[10] = if (*rq).status.status == 0 {
+                    0
+                } else {
+                    Errno::EIO.to_ret()
+                };
             }
             kfree(rq as *mut u8);
         }
@@ -416,26 +422,44 @@ struct ProcArgs {
     pub offset: u64,
 }
 
-/// This will be a
+/// This will be a kernel process reading on behalf of a user process. The
+/// device can only write into physical memory, so we read into a buffer of
+/// our own and copy it into the user's virtual memory afterwards.
 fn read_proc(args_addr: usize) {
     let args = unsafe { Box::from_raw(args_addr as *mut ProcArgs) };
-    let _ = block_op(args.dev, args.buffer, args.size, args.offset, false, args.pid);
+    let mut buffer = Buffer::with_capacity(args.size as usize);
+    let status = syscall_block_read(args.dev, buffer.as_mut_ptr(), args.size, args.offset as u32);
+    unsafe {
+        let proc = get_by_pid(args.pid);
+        if !proc.is_null() {
+            let frame = (*proc).get_frame_mut();
+            (*frame).regs[10] =
+                if copy_to_user(frame, args.buffer as usize, buffer.as_ptr(), args.size as usize).is_ok() {
+                    status
+                } else {
+                    Errno::EFAULT.to_ret()
+                };
+        }
+    }
+    with_table(|table| table.set_state(args.pid, ProcessState::Running));
     // This should be handled by the RA now.
     // syscall_exit();
 }
 
-pub fn process_read(pid: u16, dev: usize, buffer: *mut u8, size: u32, offset: u64) {
+/// Read from the block device into `buffer`, which is a virtual address in
+/// the user process `pid`. The process waits until the data has been copied.
+pub fn process_read(pid: u16, dev: usize, buffer: usize, size: u32, offset: u64) {
     // println!("Block read {}, {}, 0x{:x}, {}, {}", pid, dev, buffer as
     // usize, size, offset);
     let args = ProcArgs {
         pid,
         dev,
-        buffer,
+        buffer: buffer as *mut u8,
         size,
         offset,
     };
     let boxed_args = Box::new(args);
-    set_waiting(pid);
+    with_table(|table| table.set_state(pid, ProcessState::Waiting));
     let _ = add_kernel_process_args(read_proc, Box::into_raw(boxed_args) as usize);
 }
 
@@ -455,6 +479,6 @@ pub fn process_write(pid: u16, dev: usize, buffer: *mut u8, size: u32, offset: u
         offset,
     };
     let boxed_args = Box::new(args);
-    set_waiting(pid);
+    with_table(|table| table.set_state(pid, ProcessState::Waiting));
     let _ = add_kernel_process_args(write_proc, Box::into_raw(boxed_args) as usize);
 }
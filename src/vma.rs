@@ -0,0 +1,208 @@
+//! # Virtual memory areas
+//! Every region of a process' address space that was handed out by mmap
+//! (or by one of our own system calls, such as the framebuffer) is kept
+//! as a [`Vma`] with its permissions and what backs it. The list doubles
+//! as the allocator for new regions: a new area only goes where no other
+//! one is, so mappings can never overlap.
+use alloc::{
+    collections::BTreeMap,
+    vec::Vec,
+};
+
+use crate::{
+    fs::Inode,
+    page::EntryBits,
+};
+
+// Protection bits for mmap and mprotect
+pub const PROT_READ: usize = 1;
+pub const PROT_WRITE: usize = 2;
+pub const PROT_EXEC: usize = 4;
+
+// Flags for mmap
+pub const MAP_SHARED: usize = 0x01;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_FIXED: usize = 0x10;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+// Where mmap is allowed to put things. This starts where we used to put
+// the framebuffer, above the program and its heap, and stays well clear
+// of the stack at STACK_ADDR.
+pub const MMAP_BASE: usize = 0x3000_0000;
+pub const MMAP_END: usize = 0x8000_0000;
+
+/// What is behind the pages of an area
+#[derive(Copy, Clone)]
+pub enum Backing {
+    /// Zeroed pages of our own
+    Anonymous,
+    /// A private copy of a file, starting `offset` bytes into it
+    File { bdev: usize, inode: Inode, offset: usize },
+    /// Physical memory that belongs to a device, such as a framebuffer,
+    /// starting at this physical address. We never free these pages.
+    Device(usize),
+}
+
+/// One area of virtual memory, `[start, end)`, both page aligned.
+#[derive(Copy, Clone)]
+pub struct Vma {
+    pub start: usize,
+    pub end: usize,
+    /// PROT_READ, PROT_WRITE and PROT_EXEC
+    pub prot: usize,
+    pub backing: Backing,
+}
+
+impl Vma {
+    /// The bits to map a page of this area with. A page without any
+    /// permissions still needs one of R, W or X to be a leaf, so we
+    /// leave off the user bit instead, which makes every access from
+    /// the process fault, and keeps the page around for a later
+    /// mprotect.
+    pub fn entry_bits(&self) -> i64 {
+        if self.prot & (PROT_READ | PROT_WRITE | PROT_EXEC) == 0 {
+            return EntryBits::Read.val();
+        }
+        let mut bits = EntryBits::User.val();
+        // W without R is reserved on RISC-V, so like Linux, writable
+        // pages are readable, too.
+        if self.prot & (PROT_READ | PROT_WRITE) != 0 {
+            bits |= EntryBits::Read.val();
+        }
+        if self.prot & PROT_WRITE != 0 {
+            bits |= EntryBits::Write.val();
+        }
+        if self.prot & PROT_EXEC != 0 {
+            bits |= EntryBits::Execute.val();
+        }
+        bits
+    }
+
+    /// Whether `other` can become part of this area if they touch.
+    fn can_merge(&self, other: &Vma) -> bool {
+        matches!((self.backing, other.backing), (Backing::Anonymous, Backing::Anonymous)) && self.prot == other.prot
+    }
+
+    /// Cut this area in two at `addr`, keeping the lower half and
+    /// returning the upper one. Any offset into the backing carries over.
+    fn split_off(&mut self, addr: usize) -> Self {
+        let mut upper = *self;
+        upper.start = addr;
+        match &mut upper.backing {
+            Backing::Anonymous => {},
+            Backing::File { offset, .. } => *offset += addr - self.start,
+            Backing::Device(p_addr) => *p_addr += addr - self.start,
+        }
+        self.end = addr;
+        upper
+    }
+}
+
+/// The areas of one process, keyed by their start address.
+#[derive(Default, Clone)]
+pub struct VmaList {
+    areas: BTreeMap<usize, Vma>,
+}
+
+impl VmaList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.areas.values()
+    }
+
+    /// Get the area `addr` falls into, if any.
+    pub fn find(&self, addr: usize) -> Option<&Vma> {
+        self.areas
+            .range(..=addr)
+            .next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| addr < vma.end)
+    }
+
+    /// Check that nothing is mapped anywhere in `[start, end)`.
+    pub fn is_free(&self, start: usize, end: usize) -> bool {
+        start < end && !self.iter().any(|vma| vma.start < end && start < vma.end)
+    }
+
+    /// Find the lowest free range of `len` bytes between [`MMAP_BASE`] and
+    /// [`MMAP_END`].
+    pub fn find_free(&self, len: usize) -> Option<usize> {
+        let mut start = MMAP_BASE;
+        for vma in self.iter().filter(|vma| vma.end > MMAP_BASE) {
+            if vma.start >= start + len {
+                break;
+            }
+            start = start.max(vma.end);
+        }
+        if start + len <= MMAP_END { Some(start) } else { None }
+    }
+
+    /// Add an area. It must not overlap any other, so check with
+    /// [`VmaList::is_free`] first. An anonymous area swallows the
+    /// neighbours it touches if they have the same protection, so a heap
+    /// or stack that grows a bit at a time stays a single area.
+    pub fn insert(&mut self, mut vma: Vma) {
+        assert!(self.is_free(vma.start, vma.end));
+        if let Some(prev) = self.find(vma.start.wrapping_sub(1)).copied() {
+            if vma.can_merge(&prev) {
+                self.areas.remove(&prev.start);
+                vma.start = prev.start;
+            }
+        }
+        if let Some(next) = self.areas.get(&vma.end).copied() {
+            if vma.can_merge(&next) {
+                self.areas.remove(&next.start);
+                vma.end = next.end;
+            }
+        }
+        self.areas.insert(vma.start, vma);
+    }
+
+    /// Split any area that straddles `addr`, so that `addr` becomes the
+    /// start of an area, or falls outside all of them.
+    fn split_at(&mut self, addr: usize) {
+        if let Some(start) = self.find(addr).map(|vma| vma.start) {
+            if start != addr {
+                let upper = self.areas.get_mut(&start).unwrap().split_off(addr);
+                self.areas.insert(addr, upper);
+            }
+        }
+    }
+
+    /// Take all of `[start, end)` out of the list and call `f` on every
+    /// piece that was removed.
+    pub fn remove(&mut self, start: usize, end: usize, f: &mut dyn FnMut(&Vma)) {
+        self.split_at(start);
+        self.split_at(end);
+        let starts: Vec<usize> = self.areas.range(start..end).map(|(s, _)| *s).collect();
+        for s in starts {
+            f(&self.areas.remove(&s).unwrap());
+        }
+    }
+
+    /// Check that `[start, end)` is covered by areas without any holes.
+    pub fn is_covered(&self, start: usize, end: usize) -> bool {
+        let mut addr = start;
+        while addr < end {
+            match self.find(addr) {
+                Some(vma) => addr = vma.end,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Change the protection of all of `[start, end)`, which has to be
+    /// covered, and call `f` on every area that changed.
+    pub fn protect(&mut self, start: usize, end: usize, prot: usize, f: &mut dyn FnMut(&Vma)) {
+        self.split_at(start);
+        self.split_at(end);
+        for vma in self.areas.range_mut(start..end).map(|(_, vma)| vma) {
+            vma.prot = prot;
+            f(vma);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 #include <printf.h>
+#include <reboot.h>
 #include <syscall.h>
 
 int main() {
@@ -6,5 +7,7 @@ int main() {
   asm volatile("mv %0, sp\n" : "=r"(a));
   printf("Stack is at %p\n", a);
   printf("Hello world\n");
+  // Nothing else is going to run once we are done.
+  reboot(RB_POWER_OFF);
   return 0;
 }
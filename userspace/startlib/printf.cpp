@@ -132,7 +132,7 @@ static inline void _out_buffer(char character, void *buffer, size_t idx, size_t
 
 void _putchar(char c)
 {
-	syscall_put_char(c);
+	syscall_write(1, &c, 1);
 }
 
 // internal null output
@@ -156,6 +156,39 @@ static inline void _out_char(char character, void *buffer, size_t idx, size_t ma
 	}
 }
 
+// printf() collects its output here and writes it to stdout in pieces,
+// rather than making a system call for every character
+struct console_buffer
+{
+	char data[128];
+	size_t len;
+};
+
+static void _flush_console(console_buffer *out)
+{
+	if (out->len)
+	{
+		syscall_write(1, out->data, out->len);
+		out->len = 0;
+	}
+}
+
+// internal stdout output
+static inline void _out_console(char character, void *buffer, size_t idx, size_t maxlen)
+{
+	(void)idx;
+	(void)maxlen;
+	console_buffer *out = (console_buffer *)buffer;
+	if (character)
+	{
+		out->data[out->len++] = character;
+		if (out->len == sizeof(out->data))
+		{
+			_flush_console(out);
+		}
+	}
+}
+
 // internal output function wrapper
 static inline void _out_fct(char character, void *buffer, size_t idx, size_t maxlen)
 {
@@ -996,8 +1029,10 @@ int printf(const char *format, ...)
 {
 	va_list va;
 	va_start(va, format);
-	char buffer[1];
-	const int ret = _vsnprintf(_out_char, buffer, (size_t)-1, format, va);
+	console_buffer out;
+	out.len = 0;
+	const int ret = _vsnprintf(_out_console, (char *)&out, (size_t)-1, format, va);
+	_flush_console(&out);
 	va_end(va);
 	return ret;
 }
@@ -1022,8 +1057,11 @@ int snprintf(char *buffer, size_t count, const char *format, ...)
 
 int vprintf_(const char *format, va_list va)
 {
-	char buffer[1];
-	return _vsnprintf(_out_char, buffer, (size_t)-1, format, va);
+	console_buffer out;
+	out.len = 0;
+	const int ret = _vsnprintf(_out_console, (char *)&out, (size_t)-1, format, va);
+	_flush_console(&out);
+	return ret;
 }
 
 int vsnprintf_(char *buffer, size_t count, const char *format, va_list va)
@@ -0,0 +1,14 @@
+#pragma once
+
+#include <syscall.h>
+
+// reboot does nothing without these
+#define LINUX_REBOOT_MAGIC1     0xfee1dead
+#define LINUX_REBOOT_MAGIC2     0x28121969
+
+// What reboot can do
+#define RB_AUTOBOOT             0x01234567
+#define RB_HALT_SYSTEM          0xcdef0123
+#define RB_POWER_OFF            0x4321fedc
+
+#define reboot(howto)           syscall_reboot(LINUX_REBOOT_MAGIC1, LINUX_REBOOT_MAGIC2, howto, 0)
@@ -0,0 +1,12 @@
+#pragma once
+
+#include <syscall.h>
+
+// What setpriority and getpriority apply to
+#define PRIO_PROCESS            0
+#define PRIO_PGRP               1
+
+#define setpriority(which, who, prio)   ((long)syscall_setpriority(which, who, prio))
+// The kernel hands out 20 - nice, so that it's never negative. Errors
+// still come back negative.
+#define getpriority(which, who)         ({ long _r = syscall_getpriority(which, who); _r < 0 ? _r : 20 - _r; })
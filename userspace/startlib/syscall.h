@@ -1,3 +1,4 @@
+// Generated from the system call table in src/syscall.rs by `just syscall_header`.
 #pragma once
 
 extern "C" {
@@ -11,13 +12,107 @@ extern "C" {
         unsigned long a6=0
     );
 }
-#define syscall_exit()                  make_syscall(93)
-#define syscall_get_char()              make_syscall(1)
-#define syscall_put_char(x)             make_syscall(2, (unsigned long)x)
-#define syscall_yield()                 make_syscall(9)
-#define syscall_sleep(x)                make_syscall(10, (unsigned long)x)
-#define syscall_get_fb(x)               make_syscall(1000, (unsigned long)x)
-#define syscall_inv_rect(d, x, y, w, h) make_syscall(1001, (unsigned long) d, (unsigned long)x, (unsigned long)y, (unsigned long)w, (unsigned long)h)
-#define syscall_get_key(x, y)           make_syscall(1002, (unsigned long)x, (unsigned long)y)
-#define syscall_get_abs(x, y)           make_syscall(1004, (unsigned long)x, (unsigned long)y)
-#define syscall_get_time()              make_syscall(1062)
+
+#define SYS_put_char 2
+#define SYS_dump_registers 8
+#define SYS_yield 9
+#define SYS_sleep 10
+#define SYS_execv 11
+#define SYS_getcwd 17
+#define SYS_dup 23
+#define SYS_dup3 24
+#define SYS_fcntl 25
+#define SYS_ioctl 29
+#define SYS_chdir 49
+#define SYS_openat 56
+#define SYS_close 57
+#define SYS_pipe2 59
+#define SYS_getdents64 61
+#define SYS_lseek 62
+#define SYS_read 63
+#define SYS_write 64
+#define SYS_writev 66
+#define SYS_fstatat 79
+#define SYS_fstat 80
+#define SYS_exit 93
+#define SYS_clock_gettime 113
+#define SYS_kill 129
+#define SYS_rt_sigaction 134
+#define SYS_rt_sigprocmask 135
+#define SYS_rt_sigreturn 139
+#define SYS_setpriority 140
+#define SYS_getpriority 141
+#define SYS_reboot 142
+#define SYS_times 153
+#define SYS_setpgid 154
+#define SYS_getpgid 155
+#define SYS_uname 160
+#define SYS_gettimeofday 169
+#define SYS_getpid 172
+#define SYS_block_read 180
+#define SYS_brk 214
+#define SYS_munmap 215
+#define SYS_fork 220
+#define SYS_mmap 222
+#define SYS_mprotect 226
+#define SYS_wait4 260
+#define SYS_get_fb 1000
+#define SYS_inv_rect 1001
+#define SYS_get_key 1002
+#define SYS_get_abs 1004
+#define SYS_trace 1005
+#define SYS_stat 1038
+#define SYS_dup2 1041
+#define SYS_get_time 1062
+
+#define syscall_put_char(c) make_syscall(SYS_put_char, (unsigned long)(c))
+#define syscall_dump_registers() make_syscall(SYS_dump_registers)
+#define syscall_yield() make_syscall(SYS_yield)
+#define syscall_sleep(duration) make_syscall(SYS_sleep, (unsigned long)(duration))
+#define syscall_execv(path, argv, envp) make_syscall(SYS_execv, (unsigned long)(path), (unsigned long)(argv), (unsigned long)(envp))
+#define syscall_getcwd(buf, size) make_syscall(SYS_getcwd, (unsigned long)(buf), (unsigned long)(size))
+#define syscall_dup(oldfd) make_syscall(SYS_dup, (unsigned long)(oldfd))
+#define syscall_dup3(oldfd, newfd, flags) make_syscall(SYS_dup3, (unsigned long)(oldfd), (unsigned long)(newfd), (unsigned long)(flags))
+#define syscall_fcntl(fd, cmd, arg) make_syscall(SYS_fcntl, (unsigned long)(fd), (unsigned long)(cmd), (unsigned long)(arg))
+#define syscall_ioctl(fd, request, argp) make_syscall(SYS_ioctl, (unsigned long)(fd), (unsigned long)(request), (unsigned long)(argp))
+#define syscall_chdir(path) make_syscall(SYS_chdir, (unsigned long)(path))
+#define syscall_openat(dirfd, path, flags, mode) make_syscall(SYS_openat, (unsigned long)(dirfd), (unsigned long)(path), (unsigned long)(flags), (unsigned long)(mode))
+#define syscall_close(fd) make_syscall(SYS_close, (unsigned long)(fd))
+#define syscall_pipe2(fds, flags) make_syscall(SYS_pipe2, (unsigned long)(fds), (unsigned long)(flags))
+#define syscall_getdents64(fd, dirp, count) make_syscall(SYS_getdents64, (unsigned long)(fd), (unsigned long)(dirp), (unsigned long)(count))
+#define syscall_lseek(fd, offset, whence) make_syscall(SYS_lseek, (unsigned long)(fd), (unsigned long)(offset), (unsigned long)(whence))
+#define syscall_read(fd, buf, count) make_syscall(SYS_read, (unsigned long)(fd), (unsigned long)(buf), (unsigned long)(count))
+#define syscall_write(fd, buf, count) make_syscall(SYS_write, (unsigned long)(fd), (unsigned long)(buf), (unsigned long)(count))
+#define syscall_writev(fd, iov, iovcnt) make_syscall(SYS_writev, (unsigned long)(fd), (unsigned long)(iov), (unsigned long)(iovcnt))
+#define syscall_fstatat(dirfd, path, statbuf, flags) make_syscall(SYS_fstatat, (unsigned long)(dirfd), (unsigned long)(path), (unsigned long)(statbuf), (unsigned long)(flags))
+#define syscall_fstat(fd, statbuf) make_syscall(SYS_fstat, (unsigned long)(fd), (unsigned long)(statbuf))
+#define syscall_exit(status) make_syscall(SYS_exit, (unsigned long)(status))
+#define syscall_clock_gettime(clockid, tp) make_syscall(SYS_clock_gettime, (unsigned long)(clockid), (unsigned long)(tp))
+#define syscall_kill(pid, sig) make_syscall(SYS_kill, (unsigned long)(pid), (unsigned long)(sig))
+#define syscall_rt_sigaction(sig, act, oldact, sigsetsize) make_syscall(SYS_rt_sigaction, (unsigned long)(sig), (unsigned long)(act), (unsigned long)(oldact), (unsigned long)(sigsetsize))
+#define syscall_rt_sigprocmask(how, set, oldset, sigsetsize) make_syscall(SYS_rt_sigprocmask, (unsigned long)(how), (unsigned long)(set), (unsigned long)(oldset), (unsigned long)(sigsetsize))
+#define syscall_rt_sigreturn() make_syscall(SYS_rt_sigreturn)
+#define syscall_setpriority(which, who, prio) make_syscall(SYS_setpriority, (unsigned long)(which), (unsigned long)(who), (unsigned long)(prio))
+#define syscall_getpriority(which, who) make_syscall(SYS_getpriority, (unsigned long)(which), (unsigned long)(who))
+#define syscall_reboot(magic1, magic2, cmd, arg) make_syscall(SYS_reboot, (unsigned long)(magic1), (unsigned long)(magic2), (unsigned long)(cmd), (unsigned long)(arg))
+#define syscall_times(buf) make_syscall(SYS_times, (unsigned long)(buf))
+#define syscall_setpgid(pid, pgid) make_syscall(SYS_setpgid, (unsigned long)(pid), (unsigned long)(pgid))
+#define syscall_getpgid(pid) make_syscall(SYS_getpgid, (unsigned long)(pid))
+#define syscall_uname(buf) make_syscall(SYS_uname, (unsigned long)(buf))
+#define syscall_gettimeofday(tv, tz) make_syscall(SYS_gettimeofday, (unsigned long)(tv), (unsigned long)(tz))
+#define syscall_getpid() make_syscall(SYS_getpid)
+#define syscall_block_read(dev, buf, size, offset) make_syscall(SYS_block_read, (unsigned long)(dev), (unsigned long)(buf), (unsigned long)(size), (unsigned long)(offset))
+#define syscall_brk(addr) make_syscall(SYS_brk, (unsigned long)(addr))
+#define syscall_munmap(addr, length) make_syscall(SYS_munmap, (unsigned long)(addr), (unsigned long)(length))
+#define syscall_fork() make_syscall(SYS_fork)
+#define syscall_mmap(addr, length, prot, flags, fd, offset) make_syscall(SYS_mmap, (unsigned long)(addr), (unsigned long)(length), (unsigned long)(prot), (unsigned long)(flags), (unsigned long)(fd), (unsigned long)(offset))
+#define syscall_mprotect(addr, length, prot) make_syscall(SYS_mprotect, (unsigned long)(addr), (unsigned long)(length), (unsigned long)(prot))
+#define syscall_wait4(pid, status, options, rusage) make_syscall(SYS_wait4, (unsigned long)(pid), (unsigned long)(status), (unsigned long)(options), (unsigned long)(rusage))
+#define syscall_get_fb(dev) make_syscall(SYS_get_fb, (unsigned long)(dev))
+#define syscall_inv_rect(dev, x, y, width, height) make_syscall(SYS_inv_rect, (unsigned long)(dev), (unsigned long)(x), (unsigned long)(y), (unsigned long)(width), (unsigned long)(height))
+#define syscall_get_key(events, max) make_syscall(SYS_get_key, (unsigned long)(events), (unsigned long)(max))
+#define syscall_get_abs(events, max) make_syscall(SYS_get_abs, (unsigned long)(events), (unsigned long)(max))
+#define syscall_trace(pid, flags) make_syscall(SYS_trace, (unsigned long)(pid), (unsigned long)(flags))
+#define syscall_stat(path, statbuf) make_syscall(SYS_stat, (unsigned long)(path), (unsigned long)(statbuf))
+#define syscall_dup2(oldfd, newfd) make_syscall(SYS_dup2, (unsigned long)(oldfd), (unsigned long)(newfd))
+#define syscall_get_time() make_syscall(SYS_get_time)
@@ -0,0 +1,56 @@
+#pragma once
+
+#include <syscall.h>
+
+#define NCCS 19
+
+struct termios {
+    unsigned int c_iflag;
+    unsigned int c_oflag;
+    unsigned int c_cflag;
+    unsigned int c_lflag;
+    unsigned char c_line;
+    unsigned char c_cc[NCCS];
+};
+
+// ioctl requests
+#define TCGETS      0x5401
+#define TCSETS      0x5402
+#define TCSETSW     0x5403
+#define TCSETSF     0x5404
+#define TIOCGPGRP   0x540f
+#define TIOCSPGRP   0x5410
+
+// c_iflag
+#define ICRNL       0000400
+// c_oflag
+#define OPOST       0000001
+#define ONLCR       0000004
+// c_cflag
+#define CS8         0000060
+#define CREAD       0000200
+// c_lflag
+#define ISIG        0000001
+#define ICANON      0000002
+#define ECHO        0000010
+#define ECHOE       0000020
+#define ECHOK       0000040
+#define NOFLSH      0000200
+
+// c_cc
+#define VINTR       0
+#define VQUIT       1
+#define VERASE      2
+#define VKILL       3
+#define VEOF        4
+#define VTIME       5
+#define VMIN        6
+#define VSUSP       10
+
+// tcsetattr actions
+#define TCSANOW     0
+#define TCSADRAIN   1
+#define TCSAFLUSH   2
+
+#define tcgetattr(x, t)                 syscall_ioctl(x, TCGETS, t)
+#define tcsetattr(x, a, t)              syscall_ioctl(x, TCSETS + (a), t)